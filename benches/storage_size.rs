@@ -0,0 +1,97 @@
+//! Backend memory used by each codec, run with `cargo bench --bench storage_size --features json,msgpack`.
+//!
+//! Every key is written once, the number of keys defaults to 10M and can be set with `BRAKES_BENCH_KEYS`.
+
+use brakes::{
+    backend::{Backend, BackendError},
+    storage::Codec,
+    types::{
+        fixed_window::FixedWindow, leaky_bucket::LeakyBucket, sliding_window::SlidingWindowCounter,
+        sliding_window_log::SlidingWindowLog, token_bucket::TokenBucket, LimiterType,
+    },
+    RateLimiter,
+};
+use std::{
+    env,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+// counts the bytes written instead of storing them
+#[derive(Clone, Default)]
+struct Sizes(Arc<AtomicU64>);
+
+impl Backend for Sizes {
+    fn get(&self, _key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        Err(BackendError::KeyMissing)
+    }
+
+    fn set(&self, _key: &str, value: &[u8], _version: Option<u64>) -> Result<(), BackendError> {
+        self.0.fetch_add(value.len() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn delete(&self, _key: &str) -> Result<(), BackendError> {
+        Ok(())
+    }
+}
+
+fn measure<T: LimiterType + Clone>(name: &str, limiter: T, keys: u64) {
+    let codecs = [
+        ("bincode", Codec::Bincode),
+        ("compact", Codec::Compact),
+        #[cfg(feature = "json")]
+        ("json", Codec::Json),
+        #[cfg(feature = "msgpack")]
+        ("msgpack", Codec::MessagePack),
+    ];
+
+    for (codec_name, codec) in codecs {
+        let backend = Sizes::default();
+        let rate_limiter = RateLimiter::builder()
+            .with_backend(backend.clone())
+            .with_limiter(limiter.clone())
+            .with_codec(codec)
+            .build();
+
+        let start = Instant::now();
+        for key in 0..keys {
+            rate_limiter.is_ratelimited(&key.to_string()).unwrap();
+        }
+        let bytes = backend.0.load(Ordering::Relaxed);
+        println!(
+            "{:<24} {:<8} {:>6.1} B/key {:>10.1} MB {:>8.2?}",
+            name,
+            codec_name,
+            bytes as f64 / keys as f64,
+            bytes as f64 / 1e6,
+            start.elapsed()
+        );
+    }
+}
+
+fn main() {
+    let keys = env::var("BRAKES_BENCH_KEYS")
+        .ok()
+        .and_then(|k| k.parse().ok())
+        .unwrap_or(10_000_000);
+    let minute = Duration::from_secs(60);
+
+    println!("{} keys", keys);
+    measure("fixed_window", FixedWindow::new(100, minute), keys);
+    measure(
+        "sliding_window_counter",
+        SlidingWindowCounter::new(100, minute),
+        keys,
+    );
+    measure(
+        "sliding_window_log",
+        SlidingWindowLog::new(100, minute),
+        keys,
+    );
+    measure("token_bucket", TokenBucket::new(100, minute), keys);
+    measure("leaky_bucket", LeakyBucket::new(100, minute), keys);
+}
@@ -0,0 +1,54 @@
+#[cfg(feature = "memcache")]
+use super::memcache::MemCache;
+#[cfg(feature = "redis")]
+use super::redis::RedisBackend;
+#[cfg(feature = "redis-cluster")]
+use super::redis_cluster::RedisClusterBackend;
+use super::{local::Memory, Backend, BackendError};
+
+/// Any of the built-in backends, for when the backend is only known at runtime (see `config`).
+#[derive(Clone)]
+pub enum AnyBackend {
+    Memory(Memory),
+    #[cfg(feature = "memcache")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memcache")))]
+    MemCache(MemCache),
+    #[cfg(feature = "redis")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redis")))]
+    Redis(RedisBackend),
+    #[cfg(feature = "redis-cluster")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redis-cluster")))]
+    RedisCluster(RedisClusterBackend),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $backend:ident => $call:expr) => {
+        match $self {
+            AnyBackend::Memory($backend) => $call,
+            #[cfg(feature = "memcache")]
+            AnyBackend::MemCache($backend) => $call,
+            #[cfg(feature = "redis")]
+            AnyBackend::Redis($backend) => $call,
+            #[cfg(feature = "redis-cluster")]
+            AnyBackend::RedisCluster($backend) => $call,
+        }
+    };
+}
+
+impl Backend for AnyBackend {
+    fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        dispatch!(self, b => b.get(key))
+    }
+
+    fn set(&self, key: &str, value: &[u8], version: Option<u64>) -> Result<(), BackendError> {
+        dispatch!(self, b => b.set(key, value, version))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), BackendError> {
+        dispatch!(self, b => b.delete(key))
+    }
+
+    fn name(&self) -> &str {
+        dispatch!(self, b => b.name())
+    }
+}
@@ -45,6 +45,10 @@ impl Backend for Memory {
             Err(_) => Err(BackendError::LocalMemLockError),
         }
     }
+
+    fn name(&self) -> &str {
+        "memory"
+    }
 }
 
 impl Default for Memory {
@@ -1,4 +1,12 @@
 use super::{Backend, BackendError};
+use crate::keys::sha256_hex;
+use std::borrow::Cow;
+
+/// Prefix of the keys `MemCache` encodes, see `MemCache::encode_key`.
+pub const ENCODED_KEY_PREFIX: &str = "brakes:sha256:";
+
+// memcached's limit, in bytes
+const MAX_KEY_LENGTH: usize = 250;
 
 #[derive(Clone)]
 pub struct MemCache {
@@ -9,11 +17,28 @@ impl MemCache {
     pub fn new(client: memcache::Client) -> Self {
         MemCache { client }
     }
+
+    /// The key `key` is stored under. Memcached rejects empty keys and keys longer than 250 bytes or
+    /// containing whitespace or control characters, those are replaced by `ENCODED_KEY_PREFIX`
+    /// followed by the lowercase hex SHA-256 of the key's UTF-8 bytes. Keys already starting with the
+    /// prefix are encoded too, so that no key can be mistaken for the encoding of another one.
+    ///
+    /// Other services sharing the memcached instance can compute the same keys.
+    pub fn encode_key(key: &str) -> Cow<'_, str> {
+        let valid = !key.is_empty()
+            && key.len() <= MAX_KEY_LENGTH
+            && !key.starts_with(ENCODED_KEY_PREFIX)
+            && !key.bytes().any(|b| b <= b' ' || b == 0x7f);
+        if valid {
+            return Cow::Borrowed(key);
+        }
+        Cow::Owned(format!("{}{}", ENCODED_KEY_PREFIX, sha256_hex(key)))
+    }
 }
 
 impl Backend for MemCache {
     fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
-        let (v, _, cas) = match self.client.get(key) {
+        let (v, _, cas) = match self.client.get(&Self::encode_key(key)) {
             Ok(Some(v)) => v,
             Ok(None) => return Err(BackendError::KeyMissing),
             Err(e) => return Err(BackendError::MemCacheError(e)),
@@ -22,23 +47,52 @@ impl Backend for MemCache {
     }
 
     fn set(&self, key: &str, value: &[u8], cas: Option<u64>) -> Result<(), BackendError> {
+        let key = Self::encode_key(key);
         match cas {
-            Some(cas) => match self.client.cas(key, value, u32::MAX, cas) {
+            Some(cas) => match self.client.cas(&key, value, u32::MAX, cas) {
                 Ok(false) => Err(BackendError::ValueChanged),
                 Err(e) => Err(BackendError::MemCacheError(e)),
                 Ok(true) => Ok(()),
             },
             None => self
                 .client
-                .set(key, value, u32::MAX)
+                .set(&key, value, u32::MAX)
                 .map_err(BackendError::MemCacheError),
         }
     }
 
     fn delete(&self, key: &str) -> Result<(), BackendError> {
-        match self.client.delete(key) {
+        match self.client.delete(&Self::encode_key(key)) {
             Ok(_) => Ok(()),
             Err(e) => Err(BackendError::MemCacheError(e)),
         }
     }
+
+    fn name(&self) -> &str {
+        "memcache"
+    }
+}
+
+#[test]
+fn encode_key() {
+    for valid in ["key", "user:42", "é", &"k".repeat(250)] {
+        assert_eq!(MemCache::encode_key(valid), valid);
+    }
+
+    // SHA-256 of ""
+    let encoded = "brakes:sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    assert_eq!(MemCache::encode_key(""), encoded);
+    for invalid in [
+        "Mozilla/5.0 (X11; Linux x86_64)",
+        "tab\tkey",
+        "line\nkey",
+        "del\x7f",
+        &"k".repeat(251),
+        encoded,
+    ] {
+        let key = MemCache::encode_key(invalid);
+        assert!(key.starts_with(ENCODED_KEY_PREFIX));
+        assert_eq!(key.len(), ENCODED_KEY_PREFIX.len() + 64);
+    }
+    assert_ne!(MemCache::encode_key(encoded), encoded);
 }
@@ -1,3 +1,4 @@
+pub mod any;
 pub mod local;
 
 #[cfg(feature = "memcache")]
@@ -17,13 +18,20 @@ use ::redis::RedisError;
 use std::{
     error::Error,
     fmt::{self, Debug, Display},
+    sync::Arc,
 };
 
-pub trait Backend: Clone {
+pub trait Backend {
     fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError>;
     fn set(&self, key: &str, value: &[u8], version: Option<u64>) -> Result<(), BackendError>;
     fn delete(&self, key: &str) -> Result<(), BackendError>;
 
+    /// Identifies the backend in decision events and metrics. Built-in backends use their snake case
+    /// name, ex: `"redis"`.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+
     fn get_with_retries(
         &self,
         key: &str,
@@ -79,6 +87,47 @@ pub trait Backend: Clone {
     }
 }
 
+// lets `Arc<dyn Backend>` be used as a backend, see `DynRateLimiter`
+impl<B: Backend + ?Sized> Backend for Arc<B> {
+    fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        (**self).get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8], version: Option<u64>) -> Result<(), BackendError> {
+        (**self).set(key, value, version)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), BackendError> {
+        (**self).delete(key)
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn get_with_retries(
+        &self,
+        key: &str,
+        tries: u32,
+    ) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        (**self).get_with_retries(key, tries)
+    }
+
+    fn set_with_retries(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        version: Option<u64>,
+        tries: u32,
+    ) -> Result<(), BackendError> {
+        (**self).set_with_retries(key, value, version, tries)
+    }
+
+    fn delete_with_retries(&self, key: &str, tries: u32) -> Result<(), BackendError> {
+        (**self).delete_with_retries(key, tries)
+    }
+}
+
 #[derive(Debug)]
 pub enum BackendError {
     #[cfg(feature = "redis")]
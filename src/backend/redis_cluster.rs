@@ -61,4 +61,8 @@ impl Backend for RedisClusterBackend {
             Err(e) => Err(BackendError::R2D2Error(e)),
         }
     }
+
+    fn name(&self) -> &str {
+        "redis_cluster"
+    }
 }
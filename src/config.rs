@@ -0,0 +1,477 @@
+//! Limiters defined in configuration files.
+//!
+//! Every type here implements `serde::Deserialize`, so limits can be kept in TOML, YAML or any other
+//! format serde supports:
+//!
+//! ```toml
+//! on_failure = { retry_and_allow = 2 }
+//! on_conflict = "deny"
+//! namespace = "api"
+//!
+//! [limiter]
+//! type = "token_bucket"
+//! rate = "100/min"
+//! burst = 20
+//!
+//! [backend]
+//! type = "redis"
+//! url = "redis://127.0.0.1/"
+//! pool_size = 8
+//! ```
+//!
+//! `RateLimiterConfig::build` turns such a definition into a `RateLimiter<AnyLimiter, AnyBackend>`.
+//! Rates are written `<amount>/<period>`, ex: `"100/min"`, `"10/s"` or `"5/30s"`, and durations as a
+//! number followed by a unit, ex: `"500ms"`, `"30s"` or `"1h"` (see [`parse_duration`]). Unknown keys
+//! are rejected rather than ignored, so that a misspelled setting doesn't silently fall back to its
+//! default.
+
+use crate::{
+    backend::{any::AnyBackend, local::Memory, Backend, BackendError},
+    keys::HashTag,
+    storage::{Codec, ConfigChangePolicy},
+    types::{
+        adaptive::Adaptive,
+        any::AnyLimiter,
+        calendar::{CalendarWindow, Period},
+        concurrency::ConcurrencyLimiter,
+        fixed_window::{FixedWindow, WindowAlignment},
+        leaky_bucket::LeakyBucket,
+        sliding_window::SlidingWindowCounter,
+        sliding_window_log::SlidingWindowLog,
+        token_bucket::TokenBucket,
+        LimiterType,
+    },
+    RateLimiter, RetryStrategy,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    str::FromStr,
+    time::Duration,
+};
+
+/// A limiter, its backend and how failures are handled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimiterConfig {
+    pub limiter: LimiterConfig,
+    #[serde(default)]
+    pub backend: BackendConfig,
+    pub on_failure: Option<RetryStrategy>,
+    pub on_conflict: Option<RetryStrategy>,
+    #[serde(default = "default_discard_invalid_cache")]
+    pub discard_invalid_cache: bool,
+    #[serde(default)]
+    pub codec: Codec,
+    #[serde(default)]
+    pub on_config_change: ConfigChangePolicy,
+    /// See `RateLimiterBuilder::with_namespace`.
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub hash_tag: HashTag,
+}
+
+fn default_discard_invalid_cache() -> bool {
+    true
+}
+
+impl RateLimiterConfig {
+    /// Builds the limiter, connecting to its backend.
+    pub fn build(&self) -> Result<RateLimiter<AnyLimiter, AnyBackend>, ConfigError> {
+        self.build_with_backend(self.backend.build()?)
+    }
+
+    /// Builds the limiter on an existing backend, ignoring `backend`. Useful to share one connection
+    /// (or one `Memory` map) between many limiters, or to keep it across reloads.
+    pub fn build_with_backend<B: Backend>(
+        &self,
+        backend: B,
+    ) -> Result<RateLimiter<AnyLimiter, B>, ConfigError> {
+        let mut builder = RateLimiter::builder()
+            .with_limiter(self.limiter.build()?)
+            .with_backend(backend)
+            .with_discard_invalid_cache_entries(self.discard_invalid_cache)
+            .with_codec(self.codec)
+            .with_config_change_policy(self.on_config_change)
+            .with_hash_tag(self.hash_tag);
+        if let Some(namespace) = &self.namespace {
+            builder = builder.with_namespace(namespace);
+        }
+        if let Some(strategy) = &self.on_failure {
+            builder = builder.with_failure_strategy(strategy.clone());
+        }
+        if let Some(strategy) = &self.on_conflict {
+            builder = builder.with_conflict_strategy(strategy.clone());
+        }
+        builder
+            .try_build()
+            .map_err(|e| ConfigError::InvalidValue(e.to_string()))
+    }
+}
+
+/// One of the built-in limiters, selected by its `type`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LimiterConfig {
+    FixedWindow {
+        rate: Rate,
+        #[serde(default)]
+        alignment: WindowAlignment,
+    },
+    /// `period` is `"day"`, `"week"`, `"month"` or a duration.
+    CalendarWindow {
+        limit: u32,
+        period: CalendarPeriod,
+        utc_offset_secs: Option<i32>,
+        /// IANA timezone name, requires the `timezone` feature.
+        timezone: Option<String>,
+    },
+    SlidingWindowCounter {
+        rate: Rate,
+        #[serde(default)]
+        alignment: WindowAlignment,
+    },
+    SlidingWindowLog {
+        rate: Rate,
+    },
+    /// Refilled at `rate`, holding up to `burst` tokens (defaults to the rate's amount).
+    TokenBucket {
+        rate: Rate,
+        burst: Option<u32>,
+        initial_tokens: Option<u32>,
+    },
+    /// Leaking at `rate`, holding up to `burst` requests (defaults to the rate's amount).
+    LeakyBucket {
+        rate: Rate,
+        burst: Option<u32>,
+        #[serde(default, deserialize_with = "deserialize_optional_duration")]
+        max_queue_wait: Option<Duration>,
+    },
+    Concurrency {
+        max_concurrent: u32,
+        #[serde(deserialize_with = "deserialize_duration")]
+        lease_ttl: Duration,
+    },
+    Adaptive {
+        min_limit: u32,
+        max_limit: u32,
+        #[serde(deserialize_with = "deserialize_duration")]
+        window: Duration,
+        initial_limit: Option<u32>,
+        increase: Option<u32>,
+        decrease_factor: Option<f64>,
+        #[serde(default, deserialize_with = "deserialize_optional_duration")]
+        latency_threshold: Option<Duration>,
+    },
+}
+
+impl LimiterConfig {
+    pub fn build(&self) -> Result<AnyLimiter, ConfigError> {
+        let limiter: AnyLimiter = match self {
+            LimiterConfig::FixedWindow { rate, alignment } => {
+                FixedWindow::new(rate.amount, rate.per)
+                    .with_alignment(*alignment)
+                    .into()
+            }
+            LimiterConfig::CalendarWindow {
+                limit,
+                period,
+                utc_offset_secs,
+                timezone,
+            } => {
+                let mut limiter = CalendarWindow::new(*limit, period.0);
+                if let Some(offset) = utc_offset_secs {
+                    limiter = limiter.with_utc_offset(*offset);
+                }
+                if let Some(tz) = timezone {
+                    limiter = with_timezone(limiter, tz)?;
+                }
+                limiter.into()
+            }
+            LimiterConfig::SlidingWindowCounter { rate, alignment } => {
+                SlidingWindowCounter::new(rate.amount, rate.per)
+                    .with_alignment(*alignment)
+                    .into()
+            }
+            LimiterConfig::SlidingWindowLog { rate } => {
+                SlidingWindowLog::new(rate.amount, rate.per).into()
+            }
+            LimiterConfig::TokenBucket {
+                rate,
+                burst,
+                initial_tokens,
+            } => {
+                let mut limiter = TokenBucket::new(burst.unwrap_or(rate.amount), rate.per)
+                    .with_refill_amount(rate.amount);
+                if let Some(tokens) = initial_tokens {
+                    limiter = limiter.with_initial_tokens(*tokens);
+                }
+                limiter.into()
+            }
+            LimiterConfig::LeakyBucket {
+                rate,
+                burst,
+                max_queue_wait,
+            } => {
+                let mut limiter = LeakyBucket::new(burst.unwrap_or(rate.amount), rate.per)
+                    .with_leak_amount(rate.amount);
+                if let Some(wait) = max_queue_wait {
+                    limiter = limiter.with_max_queue_wait(*wait);
+                }
+                limiter.into()
+            }
+            LimiterConfig::Concurrency {
+                max_concurrent,
+                lease_ttl,
+            } => ConcurrencyLimiter::new(*max_concurrent, *lease_ttl).into(),
+            LimiterConfig::Adaptive {
+                min_limit,
+                max_limit,
+                window,
+                initial_limit,
+                increase,
+                decrease_factor,
+                latency_threshold,
+            } => {
+                let mut limiter = Adaptive::new(*min_limit, *max_limit, *window);
+                if let Some(limit) = initial_limit {
+                    limiter = limiter.with_initial_limit(*limit);
+                }
+                if let Some(increase) = increase {
+                    limiter = limiter.with_increase(*increase);
+                }
+                if let Some(factor) = decrease_factor {
+                    limiter = limiter.with_decrease_factor(*factor);
+                }
+                if let Some(threshold) = latency_threshold {
+                    limiter = limiter.with_latency_threshold(*threshold);
+                }
+                limiter.into()
+            }
+        };
+        limiter
+            .validate()
+            .map_err(|e| ConfigError::InvalidValue(format!("invalid {}: {}", limiter.name(), e)))?;
+        Ok(limiter)
+    }
+}
+
+#[cfg(feature = "timezone")]
+fn with_timezone(limiter: CalendarWindow, tz: &str) -> Result<CalendarWindow, ConfigError> {
+    let tz = tz
+        .parse()
+        .map_err(|_| ConfigError::InvalidValue(format!("unknown timezone: {}", tz)))?;
+    Ok(limiter.with_timezone(tz))
+}
+
+#[cfg(not(feature = "timezone"))]
+fn with_timezone(_limiter: CalendarWindow, _tz: &str) -> Result<CalendarWindow, ConfigError> {
+    Err(ConfigError::InvalidValue(
+        "timezones require the `timezone` feature".to_string(),
+    ))
+}
+
+/// One of the built-in backends, selected by its `type`. Defaults to `memory`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackendConfig {
+    #[default]
+    Memory,
+    #[cfg(feature = "memcache")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "memcache")))]
+    Memcache { url: String },
+    #[cfg(feature = "redis")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redis")))]
+    Redis {
+        url: String,
+        pool_size: Option<u32>,
+        #[serde(default, deserialize_with = "deserialize_optional_duration")]
+        connection_timeout: Option<Duration>,
+    },
+    #[cfg(feature = "redis-cluster")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redis-cluster")))]
+    RedisCluster {
+        urls: Vec<String>,
+        pool_size: Option<u32>,
+        #[serde(default, deserialize_with = "deserialize_optional_duration")]
+        connection_timeout: Option<Duration>,
+    },
+}
+
+impl BackendConfig {
+    /// Connects to the backend.
+    pub fn build(&self) -> Result<AnyBackend, ConfigError> {
+        match self {
+            BackendConfig::Memory => Ok(AnyBackend::Memory(Memory::new())),
+            #[cfg(feature = "memcache")]
+            BackendConfig::Memcache { url } => {
+                let client = memcache::connect(url.as_str())
+                    .map_err(|e| ConfigError::Backend(BackendError::MemCacheError(e)))?;
+                Ok(AnyBackend::MemCache(
+                    crate::backend::memcache::MemCache::new(client),
+                ))
+            }
+            #[cfg(feature = "redis")]
+            BackendConfig::Redis {
+                url,
+                pool_size,
+                connection_timeout,
+            } => {
+                let client = redis::Client::open(url.as_str())
+                    .map_err(|e| ConfigError::Backend(BackendError::RedisError(e)))?;
+                let pool = pool_builder(*pool_size, *connection_timeout)
+                    .build(client)
+                    .map_err(|e| ConfigError::Backend(BackendError::R2D2Error(e)))?;
+                Ok(AnyBackend::Redis(crate::backend::redis::RedisBackend::new(
+                    pool,
+                )))
+            }
+            #[cfg(feature = "redis-cluster")]
+            BackendConfig::RedisCluster {
+                urls,
+                pool_size,
+                connection_timeout,
+            } => {
+                let client = redis::cluster::ClusterClient::new(urls.clone())
+                    .map_err(|e| ConfigError::Backend(BackendError::RedisError(e)))?;
+                let pool = pool_builder(*pool_size, *connection_timeout)
+                    .build(client)
+                    .map_err(|e| ConfigError::Backend(BackendError::R2D2Error(e)))?;
+                Ok(AnyBackend::RedisCluster(
+                    crate::backend::redis_cluster::RedisClusterBackend::new(pool),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+fn pool_builder<M: r2d2::ManageConnection>(
+    size: Option<u32>,
+    timeout: Option<Duration>,
+) -> r2d2::Builder<M> {
+    let mut builder = r2d2::Pool::builder();
+    if let Some(size) = size {
+        builder = builder.max_size(size);
+    }
+    if let Some(timeout) = timeout {
+        builder = builder.connection_timeout(timeout);
+    }
+    builder
+}
+
+/// `amount` requests `per` period, parsed from `"<amount>/<period>"`, ex: `"100/min"` or `"5/30s"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub amount: u32,
+    pub per: Duration,
+}
+
+impl FromStr for Rate {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConfigError::InvalidValue(format!("invalid rate: {:?}", s));
+        let (amount, per) = s.split_once('/').ok_or_else(invalid)?;
+        let amount = amount.trim().parse().map_err(|_| invalid())?;
+        if amount == 0 {
+            return Err(invalid());
+        }
+        // the period's amount is optional, "100/min" is "100/1min"
+        let per = per.trim();
+        let per = match per.starts_with(|c: char| c.is_ascii_digit()) {
+            true => parse_duration(per)?,
+            false => parse_duration(&format!("1{}", per))?,
+        };
+        Ok(Rate { amount, per })
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// `Period` of a `CalendarWindow`, parsed from `"day"`, `"week"`, `"month"` or a duration.
+#[derive(Debug, Clone, Copy)]
+pub struct CalendarPeriod(pub Period);
+
+impl FromStr for CalendarPeriod {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let period = match s.trim() {
+            "day" | "daily" => Period::Day,
+            "week" | "weekly" => Period::Week,
+            "month" | "monthly" => Period::Month,
+            s => Period::Every(parse_duration(s)?),
+        };
+        Ok(CalendarPeriod(period))
+    }
+}
+
+impl<'de> Deserialize<'de> for CalendarPeriod {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Parses a number followed by a unit: `ms`, `s`, `m`/`min`, `h`, `d` or `w` (long forms like
+/// `seconds` work too), ex: `"500ms"` or `"1 hour"`.
+pub fn parse_duration(s: &str) -> Result<Duration, ConfigError> {
+    let invalid = || ConfigError::InvalidValue(format!("invalid duration: {:?}", s));
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let amount: u64 = s[..split].parse().map_err(|_| invalid())?;
+    let unit = match s[split..].trim() {
+        "ms" | "millis" | "millisecond" | "milliseconds" => 1,
+        "s" | "sec" | "secs" | "second" | "seconds" => 1000,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60 * 1000,
+        "h" | "hr" | "hour" | "hours" => 60 * 60 * 1000,
+        "d" | "day" | "days" => 24 * 60 * 60 * 1000,
+        "w" | "week" | "weeks" => 7 * 24 * 60 * 60 * 1000,
+        _ => return Err(invalid()),
+    };
+    amount
+        .checked_mul(unit)
+        .map(Duration::from_millis)
+        .ok_or_else(invalid)
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    parse_duration(&String::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+fn deserialize_optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => parse_duration(&s).map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// A value that can't be parsed or used, ex: a malformed rate.
+    InvalidValue(String),
+    /// The backend couldn't be connected to.
+    Backend(BackendError),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidValue(e) => write!(f, "{}", e),
+            ConfigError::Backend(e) => std::fmt::Display::fmt(&e, f),
+        }
+    }
+}
+
+impl Error for ConfigError {}
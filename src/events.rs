@@ -0,0 +1,342 @@
+//! Decision events, see `RateLimiterBuilder::on_decision`.
+//!
+//! Every call to `is_ratelimited`, `reserve`, `wait` or `acquire` produces a `Decision`, passed to each
+//! listener once the request was let through or rejected. Listeners run on the calling thread and
+//! should return quickly, ex: by incrementing counters.
+//!
+//! ```rust
+//! use brakes::{
+//!     backend::local::Memory, events::Verdict, types::fixed_window::FixedWindow, RateLimiter,
+//! };
+//! use std::{
+//!     sync::{
+//!         atomic::{AtomicU32, Ordering},
+//!         Arc,
+//!     },
+//!     time::Duration,
+//! };
+//!
+//! let limited = Arc::new(AtomicU32::new(0));
+//! let counter = limited.clone();
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(Memory::new())
+//!     .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+//!     .on_decision(move |decision| {
+//!         if decision.verdict == Verdict::Limited {
+//!             counter.fetch_add(1, Ordering::Relaxed);
+//!         }
+//!     })
+//!     .build();
+//!
+//! let _ = limiter.is_ratelimited("ip");
+//! let _ = limiter.is_ratelimited("ip");
+//! assert_eq!(limited.load(Ordering::Relaxed), 1);
+//! ```
+//!
+//! ## Tracing
+//!
+//! With the `tracing` feature, each decision runs in a `rate_limit` span at the `DEBUG` level, with
+//! the fields:
+//!
+//! - `key`: the key as stored in the backend, hashed if the limiter has a hasher (see `TracedKey`),
+//! - `limiter` and `backend`: their names,
+//! - `verdict`, `fallback` and `retries`: recorded once the request was decided, see `Decision`.
+//!
+//! Inside the span, failed backend calls and conflicting updates are `DEBUG` events, and the fallbacks
+//! (the backend kept failing, updates kept conflicting, an invalid cache entry was discarded) are
+//! `WARN` events.
+
+use crate::backend::{Backend, BackendError};
+use std::{
+    cell::Cell,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// a `tracing` event, compiled out without the `tracing` feature
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    };
+}
+
+pub(crate) use trace_event;
+
+/// What happened to a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    /// Over the limit, `RateLimiterError::RateExceeded` was returned.
+    Limited,
+    /// Rejected for any other reason, ex: a backend failure with `RetryStrategy::Deny`.
+    Failed,
+}
+
+impl Verdict {
+    /// Snake case name, ex: for metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Allowed => "allowed",
+            Verdict::Limited => "limited",
+            Verdict::Failed => "failed",
+        }
+    }
+}
+
+/// The fallback a request ended up with, when it couldn't be decided by the limiter alone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    /// The backend kept failing and `on_failure` let the request through.
+    FailOpen,
+    /// The backend kept failing and `on_failure` rejected the request.
+    FailClosed,
+    /// Concurrent updates kept conflicting and `on_conflict` let the request through.
+    ConflictAllow,
+    /// Concurrent updates kept conflicting and `on_conflict` rejected the request.
+    ConflictDeny,
+    /// The stored value couldn't be read and was discarded, the request was let through (see
+    /// `RateLimiterBuilder::with_discard_invalid_cache_entries`).
+    DiscardedInvalidCache,
+}
+
+impl Fallback {
+    /// Snake case name, ex: for metric labels.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fallback::FailOpen => "fail_open",
+            Fallback::FailClosed => "fail_closed",
+            Fallback::ConflictAllow => "conflict_allow",
+            Fallback::ConflictDeny => "conflict_deny",
+            Fallback::DiscardedInvalidCache => "discarded_invalid_cache",
+        }
+    }
+}
+
+/// A rate limiting decision, see the module documentation.
+#[derive(Debug, Clone)]
+pub struct Decision<'a> {
+    /// The key as passed to the rate limiter, before hashing (see `keys`).
+    pub key: &'a str,
+    /// `LimiterType::name` of the limiter.
+    pub limiter: &'a str,
+    /// `Backend::name` of the backend.
+    pub backend: &'a str,
+    pub verdict: Verdict,
+    pub fallback: Option<Fallback>,
+    /// Backend calls that failed or conflicted and were tried again. Failures a backend retried on
+    /// its own before succeeding (see `Backend::get_with_retries`) aren't seen.
+    pub retries: u32,
+    /// Writes that conflicted with a concurrent update of the key, retried or not.
+    pub conflicts: u32,
+    /// Time spent deciding, backend calls included.
+    pub latency: Duration,
+    /// Time spent in backend calls.
+    pub backend_latency: Duration,
+}
+
+/// How keys appear on decision spans, see `RateLimiterBuilder::with_traced_key`. Only used with the
+/// `tracing` feature.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TracedKey {
+    /// The key as passed to the rate limiter, ex: a client's IP or email address.
+    Plain,
+    /// The key as stored in the backend, ex: hashed with `keys::sha256` (see `RateLimiter::backend_key`).
+    /// The default, so that limiters hashing their keys don't send client identifiers to traces.
+    #[default]
+    Backend,
+    /// Left out of spans.
+    Redacted,
+}
+
+type Listener = dyn Fn(&Decision) + Send + Sync;
+
+#[derive(Clone, Default)]
+pub(crate) struct Listeners(Vec<Arc<Listener>>);
+
+impl Listeners {
+    pub(crate) fn add<F>(&mut self, listener: F)
+    where
+        F: Fn(&Decision) + Send + Sync + 'static,
+    {
+        self.0.push(Arc::new(listener));
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn emit(&self, decision: &Decision) {
+        for listener in &self.0 {
+            listener(decision);
+        }
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Listeners({})", self.0.len())
+    }
+}
+
+/// The `tracing` span of a decision, a no-op without the `tracing` feature.
+pub(crate) struct DecisionSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+impl DecisionSpan {
+    #[cfg(feature = "tracing")]
+    pub(crate) fn new(
+        traced_key: TracedKey,
+        key: &str,
+        backend_key: &str,
+        limiter: &str,
+        backend: &str,
+    ) -> Self {
+        let key = match traced_key {
+            TracedKey::Plain => Some(key),
+            TracedKey::Backend => Some(backend_key),
+            TracedKey::Redacted => None,
+        };
+        let span = tracing::debug_span!(
+            "rate_limit",
+            key,
+            limiter,
+            backend,
+            verdict = tracing::field::Empty,
+            fallback = tracing::field::Empty,
+            retries = tracing::field::Empty,
+        );
+        DecisionSpan { span }
+    }
+
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) fn new(_: TracedKey, _: &str, _: &str, _: &str, _: &str) -> Self {
+        DecisionSpan {}
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        #[cfg(feature = "tracing")]
+        return !self.span.is_disabled();
+        #[cfg(not(feature = "tracing"))]
+        false
+    }
+
+    pub(crate) fn in_scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(f);
+        #[cfg(not(feature = "tracing"))]
+        f()
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn record(&self, decision: &Decision) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("verdict", decision.verdict.as_str());
+            if let Some(fallback) = decision.fallback {
+                self.span.record("fallback", fallback.as_str());
+            }
+            self.span.record("retries", decision.retries);
+        }
+    }
+}
+
+/// What happened while deciding, filled in by `RateLimiter`.
+#[derive(Default)]
+pub(crate) struct Trace {
+    pub(crate) conflicts: u32,
+    pub(crate) fallback: Option<Fallback>,
+}
+
+impl Trace {
+    // the last failure or conflict before a fallback wasn't retried
+    pub(crate) fn retries(&self, backend_errors: u32) -> u32 {
+        let last = match self.fallback {
+            Some(Fallback::DiscardedInvalidCache) | None => 0,
+            Some(_) => 1,
+        };
+        (backend_errors + self.conflicts).saturating_sub(last)
+    }
+}
+
+/// Times the calls made to a backend and counts the ones that failed. Calls with retries go to the
+/// backend's own `*_with_retries`, a failed one counting as all of its tries.
+pub(crate) struct Instrumented<'a, B: ?Sized> {
+    backend: &'a B,
+    pub(crate) errors: Cell<u32>,
+    pub(crate) elapsed: Cell<Duration>,
+}
+
+impl<'a, B: Backend + ?Sized> Instrumented<'a, B> {
+    pub(crate) fn new(backend: &'a B) -> Self {
+        Instrumented {
+            backend,
+            errors: Cell::new(0),
+            elapsed: Cell::new(Duration::ZERO),
+        }
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn record<T>(
+        &self,
+        tries: u32,
+        f: impl FnOnce() -> Result<T, BackendError>,
+    ) -> Result<T, BackendError> {
+        let start = Instant::now();
+        let result = f();
+        self.elapsed.set(self.elapsed.get() + start.elapsed());
+        match &result {
+            Ok(_) | Err(BackendError::KeyMissing) | Err(BackendError::ValueChanged) => {}
+            Err(e) => {
+                trace_event!(debug, error = %e, tries, "backend call failed");
+                self.errors.set(self.errors.get() + tries);
+            }
+        }
+        result
+    }
+}
+
+impl<B: Backend + ?Sized> Backend for Instrumented<'_, B> {
+    fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        self.record(1, || self.backend.get(key))
+    }
+
+    fn set(&self, key: &str, value: &[u8], version: Option<u64>) -> Result<(), BackendError> {
+        self.record(1, || self.backend.set(key, value, version))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), BackendError> {
+        self.record(1, || self.backend.delete(key))
+    }
+
+    fn get_with_retries(
+        &self,
+        key: &str,
+        tries: u32,
+    ) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        self.record(tries, || self.backend.get_with_retries(key, tries))
+    }
+
+    fn set_with_retries(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        version: Option<u64>,
+        tries: u32,
+    ) -> Result<(), BackendError> {
+        self.record(tries, || {
+            self.backend.set_with_retries(key, value, version, tries)
+        })
+    }
+
+    fn delete_with_retries(&self, key: &str, tries: u32) -> Result<(), BackendError> {
+        self.record(tries, || self.backend.delete_with_retries(key, tries))
+    }
+
+    fn name(&self) -> &str {
+        self.backend.name()
+    }
+}
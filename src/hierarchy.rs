@@ -0,0 +1,285 @@
+use crate::{
+    backend::{Backend, BackendError},
+    storage::{Codec, Storage},
+    types::{LimiterInstance, LimiterType, RateLimiterError, SerializableInstance},
+    BuildError, RetryStrategy,
+};
+use std::{fmt, sync::Arc};
+
+type LimitFn = dyn Fn(&Storage, Option<Vec<u8>>) -> Result<Vec<u8>, RateLimiterError> + Send + Sync;
+type DecodeFn =
+    dyn Fn(&Storage, Vec<u8>) -> Result<Option<LimiterInstance>, RateLimiterError> + Send + Sync;
+type KeyFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+// a level's backend key, the value it had before the request and the value the request wrote
+type Written<'a> = (&'a str, Option<Vec<u8>>, Vec<u8>);
+
+#[derive(Clone)]
+struct Level {
+    name: String,
+    limit: Arc<LimitFn>,
+    decode: Arc<DecodeFn>,
+    key: Arc<KeyFn>,
+}
+
+/// Limits a request at several levels at once, ex: the user, the user's organization and a global limit.
+///
+/// Each level maps the request's key to the key it's limited by (or `None` if the level doesn't apply)
+/// and has its own `LimiterType`. A request is only let through if every level allows it, in which case
+/// all of them are updated; a denied request doesn't consume from any level.
+///
+/// Levels are written one after the other. If a concurrent update of a level conflicts with the write,
+/// or the write fails, the levels written before it are rolled back to their previous value, and all
+/// levels are evaluated again following the conflict `RetryStrategy`. A level updated by another
+/// request in the meantime isn't rolled back, contention can only over-count requests.
+///
+/// All levels share a single `Backend`, keys are stored as `<level name>:<level key>`.
+#[derive(Clone)]
+pub struct HierarchicalRateLimiter<B> {
+    levels: Vec<Level>,
+    backend: B,
+    on_failure: RetryStrategy,
+    on_conflict: RetryStrategy,
+    discard_invalid_cache: bool,
+    storage: Storage,
+}
+
+impl<B: Backend> HierarchicalRateLimiter<B> {
+    pub fn builder() -> HierarchicalRateLimiterBuilder<B> {
+        HierarchicalRateLimiterBuilder {
+            levels: vec![],
+            backend: None,
+            on_failure: None,
+            on_conflict: None,
+            discard_invalid_cache: true,
+            storage: Storage::default(),
+            invalid: None,
+        }
+    }
+
+    pub fn is_ratelimited(&self, key: &str) -> Result<(), RateLimiterError> {
+        let (failure_tries, allow_on_failure) = self.on_failure.tries();
+        let (conflict_tries, allow_on_conflict) = self.on_conflict.tries();
+        let backend_error = |e| {
+            if allow_on_failure {
+                return Ok(());
+            }
+            Err(RateLimiterError::BackendError(e))
+        };
+
+        // levels that apply to the key, with their backend keys
+        let levels: Vec<(&Level, String)> = self
+            .levels
+            .iter()
+            .filter_map(|level| Some((level, format!("{}:{}", level.name, (level.key)(key)?))))
+            .collect();
+
+        for _ in 0..conflict_tries {
+            // evaluate every level before writing any of them
+            let mut updates = Vec::with_capacity(levels.len());
+            for (level, key) in &levels {
+                let (value, version) = match self.backend.get_with_retries(key, failure_tries) {
+                    Ok((value, version)) => (Some(value), version),
+                    Err(BackendError::KeyMissing) => (None, None),
+                    Err(e) => return backend_error(e),
+                };
+                let instance = match (level.limit)(&self.storage, value.clone()) {
+                    Err(
+                        e @ (RateLimiterError::MalformedValue(_)
+                        | RateLimiterError::WrongLimiterInstanceType),
+                    ) => {
+                        if !self.discard_invalid_cache {
+                            return Err(e);
+                        }
+                        (level.limit)(&self.storage, None)?
+                    }
+                    result => result?,
+                };
+                updates.push((key.as_str(), value, instance, version));
+            }
+
+            // levels written so far, with the value they had before
+            let mut written = Vec::with_capacity(updates.len());
+            let mut conflict = false;
+            for (key, previous, value, version) in updates {
+                match self
+                    .backend
+                    .set_with_retries(key, value.clone(), version, failure_tries)
+                {
+                    Ok(()) => written.push((key, previous, value)),
+                    Err(BackendError::ValueChanged) => {
+                        conflict = true;
+                        break;
+                    }
+                    Err(e) => {
+                        self.roll_back(written, failure_tries);
+                        return backend_error(e);
+                    }
+                }
+            }
+            if !conflict {
+                return Ok(());
+            }
+            // all levels or none, every level is evaluated again
+            self.roll_back(written, failure_tries);
+        }
+        if allow_on_conflict {
+            return Ok(());
+        }
+        Err(RateLimiterError::BackendConflict)
+    }
+
+    // restores levels written by a request that didn't go through, unless another request updated
+    // them since: its update already counts this request, which is then over-counted rather than lost
+    fn roll_back(&self, written: Vec<Written>, tries: u32) {
+        for (key, previous, value) in written.into_iter().rev() {
+            match self.backend.get_with_retries(key, tries) {
+                Ok((current, version)) if current == value => {
+                    let _ = match previous {
+                        Some(previous) => {
+                            self.backend.set_with_retries(key, previous, version, tries)
+                        }
+                        None => self.backend.delete_with_retries(key, tries),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The stored instance of every level, in the order they were added. `None` if the level doesn't
+    /// apply to `key` or has no instance stored yet.
+    pub fn get_usage(&self, key: &str) -> Result<Vec<Option<LimiterInstance>>, RateLimiterError> {
+        self.levels
+            .iter()
+            .map(|level| {
+                let key = match (level.key)(key) {
+                    Some(k) => format!("{}:{}", level.name, k),
+                    None => return Ok(None),
+                };
+                match self.backend.get(&key) {
+                    Ok((v, _)) => (level.decode)(&self.storage, v),
+                    Err(BackendError::KeyMissing) => Ok(None),
+                    Err(e) => Err(RateLimiterError::BackendError(e)),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<B> fmt::Debug for HierarchicalRateLimiter<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HierarchicalRateLimiter")
+            .field(
+                "levels",
+                &self.levels.iter().map(|l| &l.name).collect::<Vec<_>>(),
+            )
+            .field("on_failure", &self.on_failure)
+            .field("on_conflict", &self.on_conflict)
+            .field("discard_invalid_cache", &self.discard_invalid_cache)
+            .field("storage", &self.storage)
+            .finish()
+    }
+}
+
+pub struct HierarchicalRateLimiterBuilder<B> {
+    levels: Vec<Level>,
+    backend: Option<B>,
+    on_failure: Option<RetryStrategy>,
+    on_conflict: Option<RetryStrategy>,
+    discard_invalid_cache: bool,
+    storage: Storage,
+    // first level whose limiter failed validation
+    invalid: Option<BuildError>,
+}
+
+impl<B: Backend> HierarchicalRateLimiterBuilder<B> {
+    pub fn with_backend(mut self, backend: B) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Adds a level named `name`, limiting requests by the key `key` maps them to. Levels can use
+    /// different `LimiterType`s.
+    pub fn with_level<T, K>(mut self, name: &str, limiter: T, key: K) -> Self
+    where
+        T: LimiterType + Clone + Send + Sync + 'static,
+        K: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        if let Err(e) = limiter.validate() {
+            self.invalid
+                .get_or_insert(BuildError::InvalidLimiter(limiter.name().to_string(), e));
+        }
+        let decoder = limiter.clone();
+        self.levels.push(Level {
+            name: name.to_string(),
+            limit: Arc::new(move |storage, value| {
+                let value = match value {
+                    Some(v) => storage
+                        .decode(&limiter, v)?
+                        .map(|i| i.to_bytes())
+                        .transpose()?,
+                    None => None,
+                };
+                storage.encode(&limiter, &limiter.is_ratelimited(value)?)
+            }),
+            decode: Arc::new(
+                move |storage, value| match storage.decode(&decoder, value)? {
+                    Some(i) => decoder.window_instance(i.to_bytes()?).map(Some),
+                    None => Ok(None),
+                },
+            ),
+            key: Arc::new(key),
+        });
+        self
+    }
+
+    pub fn with_failure_strategy(mut self, strategy: RetryStrategy) -> Self {
+        self.on_failure = Some(strategy);
+        self
+    }
+
+    pub fn with_conflict_strategy(mut self, strategy: RetryStrategy) -> Self {
+        self.on_conflict = Some(strategy);
+        self
+    }
+
+    pub fn with_discard_invalid_cache_entries(mut self, v: bool) -> Self {
+        self.discard_invalid_cache = v;
+        self
+    }
+
+    /// How instances are encoded in the backend, see `RateLimiterBuilder::with_codec`.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.storage.set_codec(codec);
+        self
+    }
+
+    /// Panics if the backend or the levels are missing, or if a level's limiter is invalid, see
+    /// `try_build`.
+    pub fn build(self) -> HierarchicalRateLimiter<B> {
+        match self.try_build() {
+            Ok(limiter) => limiter,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Builds the rate limiter, `BuildError::MissingLimiter` meaning that no level was added.
+    pub fn try_build(self) -> Result<HierarchicalRateLimiter<B>, BuildError> {
+        let backend = self.backend.ok_or(BuildError::MissingBackend)?;
+        if self.levels.is_empty() {
+            return Err(BuildError::MissingLimiter);
+        }
+        if let Some(e) = self.invalid {
+            return Err(e);
+        }
+
+        Ok(HierarchicalRateLimiter {
+            levels: self.levels,
+            backend,
+            on_failure: self.on_failure.unwrap_or(RetryStrategy::RetryAndAllow(2)),
+            on_conflict: self.on_conflict.unwrap_or(RetryStrategy::RetryAndDeny(2)),
+            discard_invalid_cache: self.discard_invalid_cache,
+            storage: self.storage,
+        })
+    }
+}
@@ -0,0 +1,254 @@
+//! How the keys passed to a `RateLimiter` become backend keys.
+//!
+//! Every key goes through the same steps, in this order, whichever method it's passed to
+//! (`is_ratelimited`, `acquire`, `get_usage`, ...):
+//!
+//! 1. normalizers (`RateLimiterBuilder::with_key_normalizer`), ex: lowercasing an email address,
+//! 2. the hasher (`RateLimiterBuilder::with_hasher`), ex: `keys::sha256` to keep client identifiers out
+//!    of the backend,
+//! 3. the prefix (`RateLimiterBuilder::with_key_prefix`),
+//! 4. the length cap (`RateLimiterBuilder::with_max_key_length`): longer keys are truncated and end with
+//!    16 hex digits of the 64-bit FNV-1a hash of the whole key, so that they stay distinct,
+//! 5. the namespace (`RateLimiterBuilder::with_namespace`), separated from the key by a `:`. It counts
+//!    towards the length cap but is never truncated, the key is instead.
+//!
+//! Hashers and normalizers are closures, so they can capture a salt or a tenant:
+//!
+//! ```rust
+//! use brakes::{backend::local::Memory, types::fixed_window::FixedWindow, RateLimiter};
+//! use std::time::Duration;
+//!
+//! let salt = String::from("s3cr3t");
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(Memory::new())
+//!     .with_limiter(FixedWindow::new(10, Duration::from_secs(1)))
+//!     .with_key_normalizer(|key| key.trim().to_lowercase())
+//!     .with_hasher(move |key| format!("{}:{}", salt, key.len()))
+//!     .with_key_prefix("login:")
+//!     .build();
+//!
+//! assert_eq!(limiter.backend_key(" Alice@Example.com"), "login:s3cr3t:17");
+//! ```
+//!
+//! ## Namespaces
+//!
+//! Limiters sharing a backend share its keys: two limiters keyed by client IP would read each other's
+//! instances. Giving each limiter its own namespace keeps them apart.
+//!
+//! Redis Cluster picks the slot of a key from its hash tag, the part between the first `{` and the
+//! next `}`. Namespaces can't contain braces, so they never change the slot of a key, and with
+//! `HashTag::Key` the key is wrapped in braces (`login:{10.0.0.1}`) so that a client's keys are on the
+//! same slot in every namespace. Keys that already have a hash tag are left as they are.
+
+use crate::BuildError;
+use serde::Deserialize;
+use std::{borrow::Cow, fmt, sync::Arc};
+
+type KeyFn = dyn Fn(&str) -> String + Send + Sync;
+
+// the hash ending capped keys
+const DIGEST_LEN: usize = 16;
+
+/// Which part of a namespaced key Redis Cluster hashes to pick its slot, see the module documentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HashTag {
+    /// The whole key, unless it contains a hash tag itself.
+    #[default]
+    None,
+    /// Only the key, not the namespace: `namespace:{key}`.
+    Key,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct KeyPipeline {
+    normalizers: Vec<Arc<KeyFn>>,
+    hasher: Option<Arc<KeyFn>>,
+    prefix: String,
+    max_length: Option<usize>,
+    namespace: Option<String>,
+    hash_tag: HashTag,
+}
+
+impl KeyPipeline {
+    pub(crate) fn add_normalizer<F>(&mut self, normalizer: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.normalizers.push(Arc::new(normalizer));
+    }
+
+    pub(crate) fn set_hasher<F>(&mut self, hasher: F)
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.hasher = Some(Arc::new(hasher));
+    }
+
+    pub(crate) fn set_prefix(&mut self, prefix: &str) {
+        self.prefix = prefix.to_string();
+    }
+
+    pub(crate) fn set_max_length(&mut self, max_length: usize) {
+        self.max_length = Some(max_length.max(DIGEST_LEN));
+    }
+
+    pub(crate) fn set_namespace(&mut self, namespace: &str) {
+        self.namespace = Some(namespace.to_string());
+    }
+
+    pub(crate) fn set_hash_tag(&mut self, hash_tag: HashTag) {
+        self.hash_tag = hash_tag;
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), BuildError> {
+        match &self.namespace {
+            Some(ns) if ns.is_empty() || ns.contains(['{', '}']) => {
+                Err(BuildError::InvalidNamespace(ns.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn apply<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        let mut key = Cow::Borrowed(key);
+        for normalizer in &self.normalizers {
+            key = Cow::Owned(normalizer(&key));
+        }
+        if let Some(hasher) = &self.hasher {
+            key = Cow::Owned(hasher(&key));
+        }
+        if !self.prefix.is_empty() {
+            key = Cow::Owned(format!("{}{}", self.prefix, key));
+        }
+        let tagged = self.hash_tag == HashTag::Key && !has_hash_tag(&key);
+        if let Some(max) = self.max_length {
+            // what the namespace adds, the key keeps room for its digest
+            let max = max
+                .saturating_sub(self.namespace_len(tagged))
+                .max(DIGEST_LEN);
+            if key.len() > max {
+                key = Cow::Owned(cap(&key, max));
+            }
+        }
+        match &self.namespace {
+            Some(ns) if tagged => Cow::Owned(format!("{}:{{{}}}", ns, key)),
+            Some(ns) => Cow::Owned(format!("{}:{}", ns, key)),
+            None => key,
+        }
+    }
+
+    // bytes the namespace adds to a key, along with the braces around tagged keys
+    fn namespace_len(&self, tagged: bool) -> usize {
+        match &self.namespace {
+            Some(ns) if tagged => ns.len() + 3,
+            Some(ns) => ns.len() + 1,
+            None => 0,
+        }
+    }
+}
+
+// same rule as Redis Cluster: a `{` followed by a non-empty string and a `}`
+fn has_hash_tag(key: &str) -> bool {
+    match key.find('{') {
+        Some(start) => key[start + 1..].find('}').is_some_and(|end| end > 0),
+        None => false,
+    }
+}
+
+impl fmt::Debug for KeyPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyPipeline")
+            .field("normalizers", &self.normalizers.len())
+            .field("hasher", &self.hasher.is_some())
+            .field("prefix", &self.prefix)
+            .field("max_length", &self.max_length)
+            .field("namespace", &self.namespace)
+            .field("hash_tag", &self.hash_tag)
+            .finish()
+    }
+}
+
+fn cap(key: &str, max: usize) -> String {
+    let mut end = max - DIGEST_LEN;
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{}{:016x}", &key[..end], hash)
+}
+
+/// Hex encoded SHA-256 of the key, ex: for `RateLimiterBuilder::with_hasher`.
+#[cfg(feature = "sha256")]
+#[cfg_attr(docsrs, doc(cfg(feature = "sha256")))]
+pub fn sha256(key: &str) -> String {
+    sha256_hex(key)
+}
+
+#[cfg(any(feature = "sha256", feature = "memcache"))]
+pub(crate) fn sha256_hex(key: &str) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Hex encoded 64-bit XXH3 of the key, a faster hasher for when keys only need to be shortened.
+#[cfg(feature = "xxhash")]
+#[cfg_attr(docsrs, doc(cfg(feature = "xxhash")))]
+pub fn xxh3(key: &str) -> String {
+    format!("{:016x}", xxhash_rust::xxh3::xxh3_64(key.as_bytes()))
+}
+
+#[test]
+fn key_pipeline() {
+    let mut keys = KeyPipeline::default();
+    assert!(matches!(keys.apply("key"), Cow::Borrowed("key")));
+
+    keys.add_normalizer(|k| k.to_lowercase());
+    keys.add_normalizer(|k| k.replace(' ', ""));
+    keys.set_prefix("api:");
+    assert_eq!(keys.apply("Some Key"), "api:somekey");
+
+    keys.set_max_length(24);
+    let long = "é".repeat(20);
+    let capped = keys.apply(&long);
+    assert_eq!(capped.len(), 24);
+    assert!(capped.starts_with("api:éé"));
+    assert_ne!(capped, keys.apply(&"é".repeat(21)));
+    assert_eq!(keys.apply("Short"), "api:short");
+}
+
+#[test]
+fn namespaces() {
+    let mut keys = KeyPipeline::default();
+    keys.set_namespace("login");
+    assert_eq!(keys.apply("10.0.0.1"), "login:10.0.0.1");
+
+    keys.set_hash_tag(HashTag::Key);
+    assert_eq!(keys.apply("10.0.0.1"), "login:{10.0.0.1}");
+    assert_eq!(keys.apply("user:{42}:ip"), "login:user:{42}:ip");
+    assert_eq!(keys.apply("{}"), "login:{{}}");
+    assert!(keys.validate().is_ok());
+
+    // the namespace and braces count towards the cap
+    keys.set_max_length(32);
+    let long = "a".repeat(40);
+    let capped = keys.apply(&long);
+    assert_eq!(capped.len(), 32);
+    assert!(capped.starts_with("login:{aaa"));
+    keys.set_hash_tag(HashTag::None);
+    assert_eq!(
+        keys.apply(&"a".repeat(26)),
+        format!("login:{}", "a".repeat(26))
+    );
+    assert_eq!(keys.apply(&"a".repeat(27)).len(), 32);
+
+    for invalid in ["", "{login}", "lo}gin"] {
+        keys.set_namespace(invalid);
+        assert!(keys.validate().is_err());
+    }
+}
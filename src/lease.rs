@@ -0,0 +1,68 @@
+use crate::{
+    backend::Backend,
+    types::{LimiterType, RateLimiterError},
+    RateLimiter,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A slot taken with `RateLimiter::acquire`, given back when dropped.
+///
+/// If the release fails (for example because the backend is unreachable) the slot is freed once the
+/// lease expires.
+pub struct Lease<T: LimiterType, B: Backend> {
+    held: Option<(RateLimiter<T, B>, String, u64)>,
+}
+
+impl<T: LimiterType, B: Backend> Lease<T, B> {
+    pub(crate) fn new(limiter: RateLimiter<T, B>, key: String, id: u64) -> Self {
+        Lease {
+            held: Some((limiter, key, id)),
+        }
+    }
+
+    pub(crate) fn empty() -> Self {
+        Lease { held: None }
+    }
+
+    /// `None` if the limiter doesn't hold leases.
+    pub fn id(&self) -> Option<u64> {
+        self.held.as_ref().map(|(_, _, id)| *id)
+    }
+
+    /// Gives the lease back now, reporting backend errors instead of ignoring them.
+    pub fn release(mut self) -> Result<(), RateLimiterError> {
+        match self.held.take() {
+            Some((limiter, key, id)) => limiter.release(&key, id),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: LimiterType, B: Backend> Drop for Lease<T, B> {
+    fn drop(&mut self) {
+        if let Some((limiter, key, id)) = self.held.take() {
+            let _ = limiter.release(&key, id);
+        }
+    }
+}
+
+// lease ids only need to be unique among the leases held for a single key, across processes
+pub(crate) fn next_lease_id() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos(),
+    );
+    // 0 is reserved for anonymous leases taken through `is_ratelimited`
+    hasher.finish().max(1)
+}
@@ -6,9 +6,13 @@
 //! ## Features
 //! - Support for multiple rate limiting algorithms:
 //!   - Fixed window
+//!   - Calendar-aligned quotas (daily, weekly, monthly)
 //!   - Sliding window counter
+//!   - Sliding window log
 //!   - Token bucket
 //!   - Leaky bucket
+//!   - Concurrency (in-flight leases)
+//!   - Adaptive (AIMD)
 //! - Configurable caching backends:
 //!   - Local memory
 //!   - Memcache
@@ -17,6 +21,14 @@
 //!   - [Actix Web](https://actix.rs/)
 //!   - [Axum](https://docs.rs/axum/latest/axum/)
 //! - Retry strategies
+//! - Limiters defined in configuration files (TOML, YAML, ... through serde)
+//! - Named limiters reloaded at runtime, without redeploying
+//! - Key normalization, hashing (SHA-256, xxHash or any closure), prefixes and length caps
+//! - Decision events (allowed, limited, fail-open, conflicts...) for monitoring
+//! - Prometheus metrics (`metrics` feature)
+//! - `tracing` spans and events for every decision (`tracing` feature)
+//! - OpenTelemetry span attributes and metrics in the middlewares (`otel` feature)
+//! - Portable storage formats (JSON, MessagePack) to share limits with services in other languages
 //!
 //! ## Usage
 //!
@@ -31,21 +43,19 @@
 //!     RateLimiter,
 //! };
 //!
-//! fn main() {
-//!     let limiter = RateLimiter::builder()
-//!         .with_backend(Memory::new())
-//!         .with_limiter(LeakyBucket::new(100, Duration::from_secs(10)))
-//!         .build();
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(Memory::new())
+//!     .with_limiter(LeakyBucket::new(100, Duration::from_secs(10)))
+//!     .build();
 //!
-//!     let result = limiter.is_ratelimited("key");
-//!     match &result {
-//!         Ok(()) => println!("allowed"),
-//!         Err(RateLimiterError::RateExceeded) => println!("rate exceeded"),
-//!         Err(e) => println!("error {:?}", e),
-//!     }
-//!     
-//!     assert!(result.is_ok());
+//! let result = limiter.is_ratelimited("key");
+//! match &result {
+//!     Ok(()) => println!("allowed"),
+//!     Err(RateLimiterError::RateExceeded) => println!("rate exceeded"),
+//!     Err(e) => println!("error {:?}", e),
 //! }
+//!
+//! assert!(result.is_ok());
 //! ```
 //!
 //! ### Built-in middlewares
@@ -204,6 +214,13 @@
 //!
 //! ```
 //!
+//! #### OpenTelemetry
+//!
+//! With the `otel` feature, both middlewares set `ratelimit.limiter`, `ratelimit.decision` and `ratelimit.remaining` on
+//! the current request span and count decisions in OpenTelemetry metrics, see the `middleware::otel` module.
+//! `LimiterType::remaining` tells how many requests a key still admits. Limiters are reported under the name set with
+//! the middleware's `with_name`, falling back to their registry name or, for limiters given directly, their algorithm's.
+//!
 //! ## Cache Backends
 //! Cache backends are used to store `LimiterInstance`s. A `LimiterInstance` contains information about a single rate limiter instance's (a user's or ip's) usage.
 //!
@@ -230,6 +247,8 @@
 //!
 //! If there's a conflict (data related to a single `LimiterInstance` changed while it was being updated by another process), the write is either retried (if `RetryAndAllow` or `RetryAndDeny` is used) or a `RateLimiterError::BackendConflict` is returned. In either case, whether the request is ratelimited or not is based on the `RetryStrategy` used.
 //!
+//! Keys memcached would reject (longer than 250 bytes, or containing whitespace or control characters) are stored as `brakes:sha256:` followed by the hex SHA-256 of the key, see `MemCache::encode_key`.
+//!
 //! ```rust,ignore
 //! let cache = memcache::connect("memcache://127.0.0.1:11211").unwrap();
 //! let memcache_backend = MemCache::new(cache);
@@ -283,6 +302,39 @@
 //!     .build();
 //! ```
 //!
+//! By default a key's window starts with its first request. With `with_alignment(WindowAlignment::Epoch)` windows start at multiples of `window_length` since the unix epoch instead, so all keys share the same windows (and the same reset time). `SlidingWindowCounter` supports the same option.
+//!
+//! ```rust,ignore
+//! // windows start on every full minute, for all keys
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(
+//!         FixedWindow::new(100, Duration::from_secs(60)).with_alignment(WindowAlignment::Epoch),
+//!     )
+//!     .build();
+//! ```
+//!
+//! ### CalendarWindow
+//! Defined by a `threshold` and a `Period`.
+//!
+//! `FixedWindow` starts a key's window at its first request, so every key resets at a different time. `CalendarWindow` windows are shared by all keys instead, which suits billing-style quotas:
+//! - `Period::Every(length)` windows are aligned to multiples of `length` since the unix epoch.
+//! - `Period::Day`, `Period::Week` (starting on Monday) and `Period::Month` follow the calendar.
+//!
+//! Boundaries are computed in UTC, at a fixed offset (`with_utc_offset`), or in an IANA timezone following its daylight saving transitions (`with_timezone`, **available on crate feature `timezone` only**). `window_bounds(now)` returns the current window's start and end, ex: for a `Reset` header.
+//!
+//! Keys are stored as a `FixedWindowInstance`.
+//!
+//! ```rust,ignore
+//! // 1000 calls per day, resetting at midnight in New York
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(
+//!         CalendarWindow::new(1000, Period::Day).with_timezone(chrono_tz::America::New_York),
+//!     )
+//!     .build();
+//! ```
+//!
 //! ### SlidingWindowCounter
 //! Defined by a `threshold` and a `window_length`.
 //!
@@ -297,12 +349,27 @@
 //!     .build();
 //! ```
 //!
+//! ### SlidingWindowLog
+//! Defined by a `threshold` and a `window_length`.
+//!
+//! Unlike `SlidingWindowCounter`, which approximates the previous window's share, `SlidingWindowLog` enforces the exact count in any rolling window. The `SlidingWindowLogInstance` keeps a log of request timestamps; entries older than `window_length` are dropped on every request and requests sharing a millisecond are stored as a single entry, so the log holds at most `threshold` entries.
+//!
+//! ```rust,ignore
+//! // allow at most 5 requests in any rolling 60s window.
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(SlidingWindowLog::new(5, Duration::from_secs(60)))
+//!     .build();
+//! ```
+//!
 //! ### TokenBucket
 //! Defined by a `capacity` and a `fill_frequency`.
 //!
 //! A bucket with a `capacity` of 10, and a `fill_frequency` of 1 second will allow up to 10 requests to be allowed. Each request consumes a token from the bucket. The bucket is refilled by 1 token every second. If the bucket is empty, no requests are allowed.
 //!
-//! The `TokenBucketInstance` keeps track of how many `token`s are available and the `last_access` timestamp for the user.
+//! The refill rate can be set independently of the `capacity` with `with_refill_amount`, and new buckets start full unless `with_initial_tokens` says otherwise.
+//!
+//! The `TokenBucketInstance` keeps track of how many `token`s are available and the `last_access` timestamp for the user. Tokens are stored as integers in millionths of a token, so partial refills aren't lost to rounding, even at large capacities.
 //!
 //! ```rust,ignore
 //! // 10 tokens at most, with a fill rate of 1 token every 2 seconds
@@ -310,6 +377,16 @@
 //!    .with_backend(...)
 //!    .with_limiter(TokenBucket::new(10, Duration::from_secs(2)))
 //!    .build();
+//!
+//! // 1000 requests per hour with bursts of up to 50, starting empty
+//!let hello_limiter = RateLimiter::builder()
+//!    .with_backend(...)
+//!    .with_limiter(
+//!        TokenBucket::new(50, Duration::from_secs(3600))
+//!            .with_refill_amount(1000)
+//!            .with_initial_tokens(0),
+//!    )
+//!    .build();
 //! ```
 //!
 //! ### LeakyBucket
@@ -317,6 +394,8 @@
 //!
 //! A bucket with a `capacity` of 10, and a `leak_frequency` of 1 second will allow up to 10 requests to be allowed. Each request is added to the bucket until it's full. If the bucket is full, further requests are denied until requests are leaked. A `leak_frequency` of 1 second will leak one request per second.
 //!
+//! With `with_leak_amount` the bucket leaks several requests per `leak_frequency`, spread evenly over it, for rates that aren't a whole number of milliseconds per request, ex: 3 per second.
+//!
 //! The `LeakyBucketInstance` keeps track of how many allowed requests there are in the bucket and the `last_leaked` timestamp for the user.
 //!
 //! ```rust,ignore
@@ -327,6 +406,219 @@
 //!    .build();
 //! ```
 //!
+//! #### Shaping
+//!
+//! By default `LeakyBucket` acts as a meter and rejects requests while the bucket is full. With `with_max_queue_wait` it shapes traffic instead: requests that don't fit are queued behind the ones already in the bucket, and `RateLimiter::reserve` returns the delay after which each may proceed. Requests that would wait longer than the configured maximum are rejected with `RateLimiterError::RateExceeded`.
+//!
+//! `RateLimiter::wait` blocks the current thread for that delay, and `RateLimiter::wait_async` awaits the provided sleep function so it works with any async runtime.
+//!
+//! ```rust,ignore
+//! // outbound calls at 1 per 100ms, queueing for at most 5 seconds
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(
+//!         LeakyBucket::new(1, Duration::from_millis(100))
+//!             .with_max_queue_wait(Duration::from_secs(5)),
+//!     )
+//!     .build();
+//!
+//! limiter.wait_async("upstream", tokio::time::sleep).await?;
+//! call_upstream().await;
+//! ```
+//!
+//! ### ConcurrencyLimiter
+//! Defined by a `max_concurrent` and a `lease_ttl`.
+//!
+//! Instead of limiting the rate of requests, `ConcurrencyLimiter` caps how many requests for a key are in flight at once. `RateLimiter::acquire` returns a `Lease` which gives its slot back when dropped. Leases that are never released (for example because their holder crashed) expire after `lease_ttl`, so set it comfortably above the longest expected request.
+//!
+//! The `ConcurrencyInstance` keeps track of the held leases and their expiry.
+//!
+//! Both middlewares use `acquire` and hold the lease until the inner service has responded. With any other limiter type `acquire` behaves like `is_ratelimited`.
+//!
+//! ```rust,ignore
+//! // at most 3 concurrent exports per tenant
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(ConcurrencyLimiter::new(3, Duration::from_secs(300)))
+//!     .build();
+//!
+//! let lease = limiter.acquire(tenant_id)?;
+//! run_export();
+//! drop(lease);
+//! ```
+//!
+//! ### Adaptive
+//! Defined by a `min_limit`, a `max_limit` and a `window_length`.
+//!
+//! A fixed window limiter whose threshold follows downstream health (additive increase, multiplicative decrease). Report how requests that were let through went with `RateLimiter::report(key, outcome)`: once a limit's worth of `Outcome::Success` reports came in, the limit grows by 1 (`with_increase`), and every `Outcome::Failure` multiplies it by 0.5 (`with_decrease_factor`). `Outcome::Latency` counts as a failure above the `with_latency_threshold` and as a success otherwise.
+//!
+//! The `AdaptiveInstance` keeps track of the current `limit`, the successes reported since it last changed, and the current window.
+//!
+//! The Tower middleware reports automatically: server errors and `429 Too Many Requests` responses are failures, other responses are reported with their latency.
+//!
+//! ```rust,ignore
+//! // between 10 and 1000 requests per second, halved when calls take longer than 200ms
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_limiter(
+//!         Adaptive::new(10, 1000, Duration::from_secs(1))
+//!             .with_latency_threshold(Duration::from_millis(200)),
+//!     )
+//!     .build();
+//!
+//! limiter.is_ratelimited("downstream")?;
+//! let outcome = match call_downstream() {
+//!     Ok(_) => Outcome::Success,
+//!     Err(_) => Outcome::Failure,
+//! };
+//! limiter.report("downstream", outcome)?;
+//! ```
+//!
+//! ### Custom limiter types
+//!
+//! Algorithms defined outside of this crate implement `LimiterType` and store their state as a `LimiterInstance::Custom`, identified by a `type_id`. They must also implement `LimiterType::name`, stored alongside instances and used by migrations, with a name that stays the same across releases. `LimiterInstance::custom` wraps any serializable instance, and `as_custom` reads it back, returning `RateLimiterError::WrongLimiterInstanceType` if the stored instance belongs to another algorithm. Custom instances go through the same backends and the same invalid cache handling as built-in ones.
+//!
+//! Limiters can reject parameters they can't work with by implementing `LimiterType::validate`, which `RateLimiterBuilder::try_build` calls (`build` panics instead).
+//!
+//! ```rust,ignore
+//! #[derive(Clone)]
+//! struct Lifetime(u32); // a quota that never resets
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct LifetimeInstance {
+//!     used: u32,
+//! }
+//!
+//! impl LimiterType for Lifetime {
+//!     fn is_ratelimited(&self, value: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+//!         let mut instance = match value {
+//!             Some(v) => self.window_instance(v)?.as_custom::<LifetimeInstance>("lifetime")?,
+//!             None => LifetimeInstance { used: 0 },
+//!         };
+//!         if instance.used >= self.0 {
+//!             return Err(RateLimiterError::RateExceeded);
+//!         }
+//!         instance.used += 1;
+//!         LimiterInstance::custom("lifetime", &instance)
+//!     }
+//!
+//!     fn name(&self) -> &str {
+//!         "lifetime"
+//!     }
+//! }
+//! ```
+//!
+//! ## Hierarchical Limits
+//!
+//! `HierarchicalRateLimiter` limits a request at several levels at once, ex: the user, the user's organization and a global limit. Each level maps the request's key to the key it's limited by (or `None` if the level doesn't apply to it) and has its own `LimiterType`.
+//!
+//! All levels are evaluated before any of them is written: a request is only let through if every level allows it, and a denied request doesn't consume from any level. Levels share a single `Backend`, where keys are stored as `<level name>:<level key>`.
+//!
+//! Writes to the `Backend` are checked for conflicts per key. If one is detected, levels written before it are rolled back and every level is evaluated again, following the conflict `RetryStrategy`, so that a request either consumes from all levels or from none.
+//!
+//! ```rust,ignore
+//! // keys look like "<org>/<user>"
+//! let limiter = HierarchicalRateLimiter::builder()
+//!     .with_backend(...)
+//!     .with_level("user", TokenBucket::new(10, Duration::from_secs(1)), |key| Some(key.to_string()))
+//!     .with_level("org", FixedWindow::new(100, Duration::from_secs(1)), |key| {
+//!         key.split_once('/').map(|(org, _)| org.to_string())
+//!     })
+//!     .with_level("global", FixedWindow::new(5000, Duration::from_secs(1)), |_| Some("global".to_string()))
+//!     .build();
+//!
+//! limiter.is_ratelimited("acme/alice")?;
+//! ```
+//!
+//! ## Runtime-Selected Limiters
+//!
+//! `LimiterType` and `Backend` can be used as trait objects. `RateLimiter::into_dyn` erases both types into a
+//! `DynRateLimiter`, so that limiters using different algorithms and backends can be stored together:
+//!
+//! ```rust
+//! use brakes::{
+//!     backend::local::Memory,
+//!     types::{fixed_window::FixedWindow, token_bucket::TokenBucket},
+//!     DynRateLimiter, RateLimiter,
+//! };
+//! use std::{collections::HashMap, time::Duration};
+//!
+//! let mut limiters: HashMap<&str, DynRateLimiter> = HashMap::new();
+//! limiters.insert(
+//!     "login",
+//!     RateLimiter::builder()
+//!         .with_backend(Memory::new())
+//!         .with_limiter(FixedWindow::new(5, Duration::from_secs(60)))
+//!         .build()
+//!         .into_dyn(),
+//! );
+//! limiters.insert(
+//!     "api",
+//!     RateLimiter::builder()
+//!         .with_backend(Memory::new())
+//!         .with_limiter(TokenBucket::new(100, Duration::from_millis(600)))
+//!         .build()
+//!         .into_dyn(),
+//! );
+//!
+//! assert!(limiters["login"].is_ratelimited("ip").is_ok());
+//! ```
+//!
+//! When only the built-in algorithms are needed, `types::any::AnyLimiter` (and `backend::any::AnyBackend`) do the same
+//! without dynamic dispatch.
+//!
+//! ## Configuration Files
+//!
+//! Limiters can be defined in any format serde supports, see the `config` module. `RateLimiterConfig::build` returns a
+//! `RateLimiter<AnyLimiter, AnyBackend>`, whose algorithm and backend are chosen at runtime:
+//!
+//! ```rust
+//! use brakes::config::RateLimiterConfig;
+//!
+//! let config: RateLimiterConfig = toml::from_str(
+//!     r#"
+//!     [limiter]
+//!     type = "token_bucket"
+//!     rate = "100/min"
+//!     burst = 20
+//!     "#,
+//! )
+//! .unwrap();
+//! let rate_limiter = config.build().unwrap();
+//! assert!(rate_limiter.is_ratelimited("ip").is_ok());
+//! ```
+//!
+//! ### Reloading limiters
+//!
+//! A `registry::LimiterRegistry` holds limiters by name and lets them be replaced while the service is running,
+//! ex: when a watched configuration file changes. `reload` swaps all of them at once, and leaves them untouched if
+//! any fails to build. Middlewares created with `from_registry` look their limiter up on every request:
+//!
+//! ```rust
+//! use brakes::{backend::local::Memory, config::RateLimiterConfig, registry::LimiterRegistry};
+//! use std::collections::HashMap;
+//!
+//! let registry = LimiterRegistry::new();
+//! let backend = Memory::new();
+//!
+//! let configs: HashMap<String, RateLimiterConfig> = toml::from_str(
+//!     r#"
+//!     [login.limiter]
+//!     type = "fixed_window"
+//!     rate = "5/min"
+//!
+//!     [api.limiter]
+//!     type = "token_bucket"
+//!     rate = "100/min"
+//!     "#,
+//! )
+//! .unwrap();
+//! // keeps the counters of the shared backend across reloads
+//! registry.reload_with_backend(&configs, backend.clone()).unwrap();
+//!
+//! assert!(registry.get("login").unwrap().is_ratelimited("login:ip").is_ok());
+//! ```
+//!
 //! ## Retry Strategies
 //!
 //! Retry strategies can be useful in two cases:
@@ -369,15 +661,103 @@
 //! The behavior can be changed by calling `with_discard_invalid_cache_entries(false)`.
 //! **Note:** this might cause all requests to be rate-limited (for example, if the `RateLimiter` type was changed)
 //!
+//! ## Keys
+//!
+//! Keys can be normalized, hashed (`keys::sha256` with the `sha256` feature, `keys::xxh3` with the `xxhash` feature, or
+//! any closure), prefixed and capped in length before they reach the backend, see the `keys` module.
+//! `RateLimiter::backend_key` returns the key a request is stored under.
+//!
+//! Limiters sharing a backend should each have their own namespace (`RateLimiterBuilder::with_namespace`), otherwise
+//! two limiters keyed by client IP read and overwrite each other's instances. `keys::HashTag` controls how namespaced
+//! keys are spread over the slots of a Redis Cluster.
+//!
+//! ## Decision Events
+//!
+//! `RateLimiterBuilder::on_decision` registers a listener called with every decision: the key, the algorithm, whether
+//! the request was allowed, the fallback that decided it if any (fail-open, conflict-deny, discarded invalid cache...),
+//! the retries it took and its latency. See the `events` module.
+//!
+//! With the `metrics` feature, `metrics::PrometheusMetrics` turns decisions into Prometheus counters and latency
+//! histograms labelled by limiter and backend, ex: to alert on fail-open rates.
+//!
+//! With the `tracing` feature, each decision runs in a `rate_limit` span recording the backend key (hashed if the
+//! limiter has a hasher, see `RateLimiterBuilder::with_traced_key`), the algorithm and the verdict, with events for failed backend calls,
+//! conflicts and discarded cache entries.
+//!
+//! ## Storage Format and Migrations
+//!
+//! Instances are stored in a versioned envelope (see the `storage` module) that records the name of the limiter that wrote
+//! them (`LimiterType::name`) and a fingerprint of its configuration. Entries written by earlier releases, without an
+//! envelope, are still read.
+//!
+//! To keep usage when switching limiter types, register a migration for the previous limiter. Entries it wrote are
+//! converted on their next read instead of being discarded:
+//!
+//! ```rust
+//! use brakes::{
+//!     backend::local::Memory, storage, types::token_bucket::TokenBucket, RateLimiter,
+//! };
+//! use std::time::Duration;
+//!
+//! let rate_limiter = RateLimiter::builder()
+//!     .with_backend(Memory::new())
+//!     .with_limiter(TokenBucket::new(100, Duration::from_millis(600)))
+//!     // requests counted in the current window are taken out of the bucket
+//!     .with_migration("fixed_window", storage::fixed_window_to_token_bucket(100))
+//!     .build();
+//! ```
+//!
+//! When a limiter's configuration changes (lowering a `FixedWindow` threshold from 1000 to 100, for example), entries
+//! written under the previous configuration are kept as is by default. `with_config_change_policy` can instead rescale
+//! them proportionally (`ConfigChangePolicy::Rescale`, 500 requests out of 1000 become 50 out of 100) or discard them
+//! (`ConfigChangePolicy::Reset`).
+//!
+//! Instances are encoded with `bincode` by default. `with_codec` selects another `storage::Codec`, such as
+//! `Codec::Json` (`json` feature) or `Codec::MessagePack` (`msgpack` feature) for limits shared with services written in
+//! other languages, or `Codec::Compact` to cut backend memory by about three quarters. The schema of every algorithm,
+//! and the size of each codec, is documented in the `storage` module.
+//!
 pub mod backend;
+pub mod config;
+pub mod events;
+pub mod hierarchy;
+pub mod keys;
+mod lease;
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
 pub mod middleware;
+pub mod registry;
+pub mod storage;
 pub mod types;
 
+pub use lease::Lease;
+
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    future::Future,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
 use crate::{
     backend::{Backend, BackendError},
+    events::{
+        trace_event, Decision, DecisionSpan, Fallback, Instrumented, Listeners, Trace, TracedKey,
+        Verdict,
+    },
+    keys::{HashTag, KeyPipeline},
+    storage::{Codec, ConfigChangePolicy, Storage},
     types::LimiterType,
 };
-use types::{LimiterInstance, RateLimiterError, SerializableInstance};
+use types::{InvalidParameter, LimiterInstance, Outcome, RateLimiterError, SerializableInstance};
+
+/// A `RateLimiter` whose limiter and backend are picked at runtime, see `RateLimiter::into_dyn`.
+pub type DynRateLimiter =
+    RateLimiter<Arc<dyn LimiterType + Send + Sync>, Arc<dyn Backend + Send + Sync>>;
 
 #[derive(Debug, Clone)]
 pub struct RateLimiter<T, B> {
@@ -386,7 +766,10 @@ pub struct RateLimiter<T, B> {
     on_failure: RetryStrategy,
     on_conflict: RetryStrategy,
     discard_invalid_cache: bool,
-    hasher: Option<fn(&str) -> String>,
+    keys: KeyPipeline,
+    storage: Storage,
+    listeners: Listeners,
+    traced_key: TracedKey,
 }
 
 impl<T: LimiterType, B: Backend> RateLimiter<T, B> {
@@ -397,68 +780,276 @@ impl<T: LimiterType, B: Backend> RateLimiter<T, B> {
             on_failure: None,
             on_conflict: None,
             discard_invalid_cache: true,
-            hasher: None,
+            keys: KeyPipeline::default(),
+            storage: Storage::default(),
+            listeners: Listeners::default(),
+            traced_key: TracedKey::default(),
         }
     }
 
     pub fn is_ratelimited(&self, key: &str) -> Result<(), RateLimiterError> {
-        let key = match self.hasher {
-            Some(h) => &(h)(key),
-            None => key,
+        self.update(key, |value| self.limiter.is_ratelimited(value))
+            .map(|_| ())
+    }
+
+    /// Admits the request and returns how long it has to wait before proceeding.
+    ///
+    /// The delay is zero unless the limiter shapes traffic (see `LeakyBucket::with_max_queue_wait`).
+    pub fn reserve(&self, key: &str) -> Result<Duration, RateLimiterError> {
+        let instance = self.update(key, |value| self.limiter.is_ratelimited(value))?;
+        Ok(instance.map_or(Duration::ZERO, |i| self.limiter.delay(&i)))
+    }
+
+    /// Like `reserve`, but blocks the current thread for the returned delay.
+    pub fn wait(&self, key: &str) -> Result<(), RateLimiterError> {
+        let delay = self.reserve(key)?;
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+
+    /// Like `reserve`, but awaits `sleep(delay)` before returning, ex: `wait_async(key, tokio::time::sleep)`.
+    pub async fn wait_async<F, Fut>(&self, key: &str, sleep: F) -> Result<(), RateLimiterError>
+    where
+        F: FnOnce(Duration) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let delay = self.reserve(key)?;
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        Ok(())
+    }
+
+    /// Like `is_ratelimited`, but for limiters that hold on to what a request consumes
+    /// (`ConcurrencyLimiter`) the returned `Lease` gives it back when dropped.
+    ///
+    /// For every other limiter the `Lease` is a no-op.
+    pub fn acquire(&self, key: &str) -> Result<Lease<T, B>, RateLimiterError>
+    where
+        T: Clone,
+        B: Clone,
+    {
+        self.admit(key).map(|(lease, _)| lease)
+    }
+
+    // `acquire`, also returning the requests the key still admits (see `LimiterType::remaining`)
+    pub(crate) fn admit(&self, key: &str) -> Result<(Lease<T, B>, Option<u32>), RateLimiterError>
+    where
+        T: Clone,
+        B: Clone,
+    {
+        let remaining = |instance: Option<LimiterInstance>| {
+            instance.and_then(|instance| self.limiter.remaining(&instance))
         };
+        if !self.limiter.holds_leases() {
+            let instance = self.update(key, |value| self.limiter.is_ratelimited(value))?;
+            return Ok((Lease::empty(), remaining(instance)));
+        }
+        let id = lease::next_lease_id();
+        let instance = self.update(key, |value| self.limiter.acquire(value, id))?;
+        Ok((
+            Lease::new(self.clone(), key.to_string(), id),
+            remaining(instance),
+        ))
+    }
 
-        let (failure_tries, allow_on_failure) = match self.on_failure {
-            RetryStrategy::RetryAndAllow(retries) => (retries + 1, true),
-            RetryStrategy::RetryAndDeny(retries) => (retries + 1, false),
-            RetryStrategy::Allow => (1, true),
-            RetryStrategy::Deny => (1, false),
+    pub fn get_usage(&self, key: &str) -> Result<LimiterInstance, RateLimiterError> {
+        let value = match self.backend.get(&self.keys.apply(key)) {
+            Ok((v, _)) => v,
+            Err(e) => return Err(RateLimiterError::BackendError(e)),
         };
-        let (conflict_tries, allow_on_conflict) = match self.on_conflict {
-            RetryStrategy::RetryAndAllow(retries) => (retries + 1, true),
-            RetryStrategy::RetryAndDeny(retries) => (retries + 1, false),
-            RetryStrategy::Allow => (1, true),
-            RetryStrategy::Deny => (1, false),
+        match self.read(Some(value))? {
+            Some(v) => self.limiter.window_instance(v),
+            None => Err(RateLimiterError::BackendError(BackendError::KeyMissing)),
+        }
+    }
+
+    /// Reports how a request that was let through went, for limiters that adapt to it (`Adaptive`).
+    ///
+    /// For every other limiter this is a no-op.
+    pub fn report(&self, key: &str, outcome: Outcome) -> Result<(), RateLimiterError> {
+        if !self.limiter.wants_feedback() {
+            return Ok(());
+        }
+        self.amend(key, |value| self.limiter.report(value, &outcome))
+    }
+
+    /// The key `key` is stored under in the backend, see the `keys` module.
+    pub fn backend_key(&self, key: &str) -> String {
+        self.keys.apply(key).into_owned()
+    }
+
+    #[cfg(feature = "tower")]
+    pub(crate) fn wants_feedback(&self) -> bool {
+        self.limiter.wants_feedback()
+    }
+
+    pub(crate) fn release(&self, key: &str, lease: u64) -> Result<(), RateLimiterError> {
+        self.amend(key, |value| self.limiter.release(value, lease))
+    }
+
+    /// Erases the limiter and backend types, so that limiters using different ones can be stored
+    /// together, ex: in a `HashMap<String, DynRateLimiter>`.
+    pub fn into_dyn(self) -> DynRateLimiter
+    where
+        T: Send + Sync + 'static,
+        B: Send + Sync + 'static,
+    {
+        RateLimiter {
+            limiter: Arc::new(self.limiter),
+            backend: Arc::new(self.backend),
+            on_failure: self.on_failure,
+            on_conflict: self.on_conflict,
+            discard_invalid_cache: self.discard_invalid_cache,
+            keys: self.keys,
+            storage: self.storage,
+            listeners: self.listeners,
+            traced_key: self.traced_key,
+        }
+    }
+
+    // updates an existing instance without limiting; missing keys are left alone
+    fn amend<F>(&self, key: &str, f: F) -> Result<(), RateLimiterError>
+    where
+        F: Fn(Vec<u8>) -> Result<Option<LimiterInstance>, RateLimiterError>,
+    {
+        let key = self.keys.apply(key);
+        let (failure_tries, _) = self.on_failure.tries();
+        let (conflict_tries, _) = self.on_conflict.tries();
+
+        for _ in 0..conflict_tries {
+            let (value, version) = match self.backend.get_with_retries(&key, failure_tries) {
+                Ok(v) => v,
+                Err(BackendError::KeyMissing) => return Ok(()),
+                Err(e) => return Err(RateLimiterError::BackendError(e)),
+            };
+            let value = match self.read(Some(value))? {
+                Some(v) => v,
+                None => return Ok(()),
+            };
+            let instance = match f(value)? {
+                Some(instance) => instance,
+                None => return Ok(()),
+            };
+            match self.backend.set_with_retries(
+                &key,
+                self.storage.encode(&self.limiter, &instance)?,
+                version,
+                failure_tries,
+            ) {
+                Ok(()) => return Ok(()),
+                Err(BackendError::ValueChanged) => continue,
+                Err(e) => return Err(RateLimiterError::BackendError(e)),
+            }
+        }
+        Err(RateLimiterError::BackendConflict)
+    }
+
+    // unwraps a stored value into the bytes the limiter reads, `None` if the key starts over
+    fn read(&self, value: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, RateLimiterError> {
+        match value {
+            Some(v) => match self.storage.decode(&self.limiter, v)? {
+                Some(instance) => instance.to_bytes().map(Some),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // returns the instance written to the backend, or `None` if the request was let through without one
+    fn update<F>(&self, key: &str, limit: F) -> Result<Option<LimiterInstance>, RateLimiterError>
+    where
+        F: Fn(Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError>,
+    {
+        let backend_key = self.keys.apply(key);
+        let mut trace = Trace::default();
+        let span = DecisionSpan::new(
+            self.traced_key,
+            key,
+            &backend_key,
+            self.limiter.name(),
+            self.backend.name(),
+        );
+        if self.listeners.is_empty() && !span.is_enabled() {
+            return span.in_scope(|| self.decide(&self.backend, &backend_key, limit, &mut trace));
+        }
+
+        let start = Instant::now();
+        let backend = Instrumented::new(&self.backend);
+        let result = span.in_scope(|| self.decide(&backend, &backend_key, limit, &mut trace));
+        let decision = Decision {
+            key,
+            limiter: self.limiter.name(),
+            backend: self.backend.name(),
+            verdict: match &result {
+                Ok(_) => Verdict::Allowed,
+                Err(RateLimiterError::RateExceeded) => Verdict::Limited,
+                Err(_) => Verdict::Failed,
+            },
+            fallback: trace.fallback,
+            retries: trace.retries(backend.errors.get()),
+            conflicts: trace.conflicts,
+            latency: start.elapsed(),
+            backend_latency: backend.elapsed.get(),
         };
+        span.record(&decision);
+        self.listeners.emit(&decision);
+        result
+    }
+
+    fn decide<F>(
+        &self,
+        backend: &(impl Backend + ?Sized),
+        key: &str,
+        limit: F,
+        trace: &mut Trace,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError>
+    where
+        F: Fn(Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError>,
+    {
+        let (failure_tries, allow_on_failure) = self.on_failure.tries();
+        let (conflict_tries, allow_on_conflict) = self.on_conflict.tries();
 
         for _ in 0..conflict_tries {
-            let (value, version) = match self.backend.get_with_retries(key, failure_tries) {
+            let (value, version) = match backend.get_with_retries(key, failure_tries) {
                 Ok((value, version)) => (Some(value), version),
                 Err(BackendError::KeyMissing) => (None, None),
                 Err(e) => {
-                    if allow_on_failure {
-                        return Ok(());
-                    }
-                    return Err(RateLimiterError::BackendError(e));
+                    return self.fail(e, allow_on_failure, trace);
                 }
             };
-            let updated_limiter = self.limiter.is_ratelimited(value);
+            let updated_limiter = self.read(value).and_then(&limit);
             match updated_limiter {
                 Ok(v) => {
-                    match self
-                        .backend
-                        .set_with_retries(key, v.to_bytes()?, version, failure_tries)
-                    {
-                        Ok(()) => return Ok(()),
+                    match backend.set_with_retries(
+                        key,
+                        self.storage.encode(&self.limiter, &v)?,
+                        version,
+                        failure_tries,
+                    ) {
+                        Ok(()) => return Ok(Some(v)),
                         Err(BackendError::ValueChanged) => {
+                            trace.conflicts += 1;
+                            trace_event!(debug, "update conflicted with a concurrent one");
                             continue;
                         }
                         Err(e) => {
-                            if allow_on_failure {
-                                return Ok(());
-                            }
-                            return Err(RateLimiterError::BackendError(e));
+                            return self.fail(e, allow_on_failure, trace);
                         }
                     }
                 }
                 Err(RateLimiterError::MalformedValue(e)) => {
                     if self.discard_invalid_cache {
-                        match self.backend.delete_with_retries(key, failure_tries) {
-                            Ok(_) => return Ok(()),
+                        match backend.delete_with_retries(key, failure_tries) {
+                            Ok(_) => {
+                                trace.fallback = Some(Fallback::DiscardedInvalidCache);
+                                trace_event!(warn, error = %e, "discarded a malformed cache entry");
+                                return Ok(None);
+                            }
                             Err(e) => {
-                                if allow_on_failure {
-                                    return Ok(());
-                                }
-                                return Err(RateLimiterError::BackendError(e));
+                                return self.fail(e, allow_on_failure, trace);
                             }
                         }
                     }
@@ -466,13 +1057,17 @@ impl<T: LimiterType, B: Backend> RateLimiter<T, B> {
                 }
                 Err(RateLimiterError::WrongLimiterInstanceType) => {
                     if self.discard_invalid_cache {
-                        match self.backend.delete_with_retries(key, failure_tries) {
-                            Ok(_) => return Ok(()),
+                        match backend.delete_with_retries(key, failure_tries) {
+                            Ok(_) => {
+                                trace.fallback = Some(Fallback::DiscardedInvalidCache);
+                                trace_event!(
+                                    warn,
+                                    "discarded a cache entry of another limiter type"
+                                );
+                                return Ok(None);
+                            }
                             Err(e) => {
-                                if allow_on_failure {
-                                    return Ok(());
-                                }
-                                return Err(RateLimiterError::BackendError(e));
+                                return self.fail(e, allow_on_failure, trace);
                             }
                         }
                     }
@@ -482,17 +1077,30 @@ impl<T: LimiterType, B: Backend> RateLimiter<T, B> {
             }
         }
         if allow_on_conflict {
-            return Ok(());
+            trace.fallback = Some(Fallback::ConflictAllow);
+            trace_event!(warn, "updates kept conflicting, request allowed");
+            return Ok(None);
         }
+        trace.fallback = Some(Fallback::ConflictDeny);
+        trace_event!(warn, "updates kept conflicting, request denied");
         Err(RateLimiterError::BackendConflict)
     }
 
-    pub fn get_usage(&self, key: &str) -> Result<LimiterInstance, RateLimiterError> {
-        let value = match self.backend.get(key) {
-            Ok((v, _)) => v,
-            Err(e) => return Err(RateLimiterError::BackendError(e)),
-        };
-        self.limiter.window_instance(value)
+    // applies `on_failure` once the backend failed every try
+    fn fail(
+        &self,
+        e: BackendError,
+        allow_on_failure: bool,
+        trace: &mut Trace,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        if allow_on_failure {
+            trace.fallback = Some(Fallback::FailOpen);
+            trace_event!(warn, error = %e, "backend failed, request allowed");
+            return Ok(None);
+        }
+        trace.fallback = Some(Fallback::FailClosed);
+        trace_event!(warn, error = %e, "backend failed, request denied");
+        Err(RateLimiterError::BackendError(e))
     }
 }
 
@@ -502,7 +1110,10 @@ pub struct RateLimiterBuilder<C, B> {
     on_failure: Option<RetryStrategy>,
     on_conflict: Option<RetryStrategy>,
     discard_invalid_cache: bool,
-    hasher: Option<fn(&str) -> String>,
+    keys: KeyPipeline,
+    storage: Storage,
+    listeners: Listeners,
+    traced_key: TracedKey,
 }
 
 impl<C, B> RateLimiterBuilder<C, B>
@@ -530,8 +1141,50 @@ where
         self
     }
 
-    pub fn with_hasher(mut self, hasher: fn(&str) -> String) -> Self {
-        self.hasher = Some(hasher);
+    /// Hashes keys before they reach the backend, ex: `keys::sha256` (`sha256` feature) or a closure
+    /// capturing a salt. See the `keys` module for the order keys are transformed in.
+    pub fn with_hasher<F>(mut self, hasher: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.keys.set_hasher(hasher);
+        self
+    }
+
+    /// Transforms keys before they're hashed, ex: `|key| key.to_lowercase()`. Normalizers run in the
+    /// order they're added.
+    pub fn with_key_normalizer<F>(mut self, normalizer: F) -> Self
+    where
+        F: Fn(&str) -> String + Send + Sync + 'static,
+    {
+        self.keys.add_normalizer(normalizer);
+        self
+    }
+
+    /// Prepended to every (hashed) key.
+    pub fn with_key_prefix(mut self, prefix: &str) -> Self {
+        self.keys.set_prefix(prefix);
+        self
+    }
+
+    /// Keeps the limiter's keys apart from other limiters sharing the backend, keys become
+    /// `namespace:key`. Namespaces can't be empty or contain braces, see `keys::HashTag`.
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.keys.set_namespace(namespace);
+        self
+    }
+
+    /// Which part of namespaced keys Redis Cluster picks their slot from, defaults to `HashTag::None`.
+    pub fn with_hash_tag(mut self, hash_tag: HashTag) -> Self {
+        self.keys.set_hash_tag(hash_tag);
+        self
+    }
+
+    /// Caps backend keys at `max_length` bytes, namespace included, longer ones are truncated and end
+    /// with a hash of the whole key. The namespace is never truncated, keys keep at least 16 bytes
+    /// after it.
+    pub fn with_max_key_length(mut self, max_length: usize) -> Self {
+        self.keys.set_max_length(max_length);
         self
     }
 
@@ -540,37 +1193,121 @@ where
         self
     }
 
-    pub fn build(mut self) -> RateLimiter<C, B> {
-        if self.backend.is_none() {
-            panic!("no backend specified");
-        }
-        if self.limiter.is_none() {
-            panic!("no limiter specified");
-        }
+    /// Converts instances written by the limiter named `from` (see `LimiterType::name`) instead of
+    /// discarding them, ex: `with_migration("fixed_window", storage::fixed_window_to_token_bucket(100))`.
+    pub fn with_migration<F>(mut self, from: &str, migration: F) -> Self
+    where
+        F: Fn(LimiterInstance) -> Option<LimiterInstance> + Send + Sync + 'static,
+    {
+        self.storage.add_migration(from, migration);
+        self
+    }
 
-        if self.on_failure.is_none() {
-            self.on_failure = Some(RetryStrategy::RetryAndAllow(2))
-        }
+    /// What to do with usage stored under a previous configuration of the limiter, defaults to
+    /// `ConfigChangePolicy::Keep`.
+    pub fn with_config_change_policy(mut self, policy: ConfigChangePolicy) -> Self {
+        self.storage.set_config_change_policy(policy);
+        self
+    }
+
+    /// How instances are encoded in the backend, defaults to `Codec::Bincode`. See the `storage` module
+    /// for the formats.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.storage.set_codec(codec);
+        self
+    }
 
-        if self.on_conflict.is_none() {
-            self.on_conflict = Some(RetryStrategy::RetryAndDeny(2))
+    /// Calls `listener` with every decision the rate limiter makes, see the `events` module. Can be
+    /// called several times to add several listeners.
+    pub fn on_decision<F>(mut self, listener: F) -> Self
+    where
+        F: Fn(&Decision) + Send + Sync + 'static,
+    {
+        self.listeners.add(listener);
+        self
+    }
+
+    /// How keys appear on the spans of decisions with the `tracing` feature, defaults to
+    /// `TracedKey::Backend`. See the `events` module.
+    pub fn with_traced_key(mut self, traced_key: TracedKey) -> Self {
+        self.traced_key = traced_key;
+        self
+    }
+
+    /// Panics if the backend or the limiter is missing, or if the limiter's parameters are invalid,
+    /// see `try_build`.
+    pub fn build(self) -> RateLimiter<C, B> {
+        match self.try_build() {
+            Ok(limiter) => limiter,
+            Err(e) => panic!("{}", e),
         }
+    }
 
-        RateLimiter {
-            backend: self.backend.unwrap(),
-            limiter: self.limiter.unwrap(),
-            on_failure: self.on_failure.unwrap(),
-            on_conflict: self.on_conflict.unwrap(),
+    /// Builds the rate limiter, checking that the backend and the limiter were specified and that the
+    /// limiter's parameters are valid (see `LimiterType::validate`).
+    pub fn try_build(self) -> Result<RateLimiter<C, B>, BuildError> {
+        let backend = self.backend.ok_or(BuildError::MissingBackend)?;
+        let limiter = self.limiter.ok_or(BuildError::MissingLimiter)?;
+        self.keys.validate()?;
+        limiter
+            .validate()
+            .map_err(|e| BuildError::InvalidLimiter(limiter.name().to_string(), e))?;
+
+        Ok(RateLimiter {
+            backend,
+            limiter,
+            on_failure: self.on_failure.unwrap_or(RetryStrategy::RetryAndAllow(2)),
+            on_conflict: self.on_conflict.unwrap_or(RetryStrategy::RetryAndDeny(2)),
             discard_invalid_cache: self.discard_invalid_cache,
-            hasher: self.hasher,
+            keys: self.keys,
+            storage: self.storage,
+            listeners: self.listeners,
+            traced_key: self.traced_key,
+        })
+    }
+}
+
+/// Why `RateLimiterBuilder::try_build` failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    MissingBackend,
+    MissingLimiter,
+    /// The limiter, by name, and the parameter it rejected.
+    InvalidLimiter(String, InvalidParameter),
+    /// An empty namespace, or one containing a Redis Cluster hash tag.
+    InvalidNamespace(String),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::MissingBackend => write!(f, "no backend specified"),
+            BuildError::MissingLimiter => write!(f, "no limiter specified"),
+            BuildError::InvalidLimiter(limiter, e) => write!(f, "invalid {}: {}", limiter, e),
+            BuildError::InvalidNamespace(ns) => write!(f, "invalid namespace: {:?}", ns),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl Error for BuildError {}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RetryStrategy {
     RetryAndAllow(u32),
     RetryAndDeny(u32),
     Allow,
     Deny,
 }
+
+impl RetryStrategy {
+    // total number of tries, and whether to allow the request if all of them fail
+    fn tries(&self) -> (u32, bool) {
+        match self {
+            RetryStrategy::RetryAndAllow(retries) => (retries + 1, true),
+            RetryStrategy::RetryAndDeny(retries) => (retries + 1, false),
+            RetryStrategy::Allow => (1, true),
+            RetryStrategy::Deny => (1, false),
+        }
+    }
+}
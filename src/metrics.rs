@@ -0,0 +1,192 @@
+//! Prometheus metrics, built from decision events (see `events`).
+//!
+//! `PrometheusMetrics::listener` returns a decision listener recording, for each limiter and backend:
+//!
+//! - `brakes_decisions_total{limiter, backend, verdict}`: decisions by verdict, `allowed`, `limited` or
+//!   `failed`,
+//! - `brakes_fallbacks_total{limiter, backend, fallback}`: decisions taken by a fallback, `fail_open`,
+//!   `fail_closed`, `conflict_allow`, `conflict_deny` or `discarded_invalid_cache`,
+//! - `brakes_retries_total{limiter, backend}`: backend calls tried again after a failure or conflict,
+//! - `brakes_conflicts_total{limiter, backend}`: writes that conflicted with a concurrent update,
+//! - `brakes_decision_duration_seconds{limiter, backend}`: decision latency, backend calls included,
+//! - `brakes_backend_duration_seconds{limiter, backend}`: time spent in backend calls per decision.
+//!
+//! ```rust
+//! use brakes::{
+//!     backend::local::Memory, metrics::PrometheusMetrics, types::fixed_window::FixedWindow,
+//!     RateLimiter,
+//! };
+//! use std::time::Duration;
+//!
+//! let metrics = PrometheusMetrics::new();
+//! let limiter = RateLimiter::builder()
+//!     .with_backend(Memory::new())
+//!     .with_limiter(FixedWindow::new(10, Duration::from_secs(1)))
+//!     .on_decision(metrics.listener("login"))
+//!     .build();
+//!
+//! limiter.is_ratelimited("ip").unwrap();
+//!
+//! // served on the /metrics endpoint
+//! let text = metrics.render().unwrap();
+//! assert!(text.contains(
+//!     r#"brakes_decisions_total{backend="memory",limiter="login",verdict="allowed"} 1"#
+//! ));
+//! ```
+
+use crate::events::Decision;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+
+// decisions on a local backend take microseconds, on a remote one milliseconds
+const BUCKETS: [f64; 14] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+/// Rate limiting metrics, registered in a `prometheus::Registry`. Clones share the same metrics.
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    registry: Registry,
+    decisions: IntCounterVec,
+    fallbacks: IntCounterVec,
+    retries: IntCounterVec,
+    conflicts: IntCounterVec,
+    latency: HistogramVec,
+    backend_latency: HistogramVec,
+}
+
+impl PrometheusMetrics {
+    /// Metrics in a registry of their own, see `render`.
+    pub fn new() -> Self {
+        // can only fail on name conflicts, and the registry is empty
+        Self::with_registry(&Registry::new()).expect("metrics registered twice")
+    }
+
+    /// Registers the metrics in `registry`, ex: the one the rest of the service's metrics are in.
+    /// Fails if any of them was already registered in it, leaving the registry as it was.
+    pub fn with_registry(registry: &Registry) -> prometheus::Result<Self> {
+        let labels = ["limiter", "backend"];
+        let metrics = PrometheusMetrics {
+            registry: registry.clone(),
+            decisions: IntCounterVec::new(
+                Opts::new(
+                    "brakes_decisions_total",
+                    "Rate limiting decisions by verdict.",
+                ),
+                &["limiter", "backend", "verdict"],
+            )?,
+            fallbacks: IntCounterVec::new(
+                Opts::new(
+                    "brakes_fallbacks_total",
+                    "Decisions taken by a retry strategy or an invalid cache discard.",
+                ),
+                &["limiter", "backend", "fallback"],
+            )?,
+            retries: IntCounterVec::new(
+                Opts::new(
+                    "brakes_retries_total",
+                    "Backend calls tried again after a failure or a conflict.",
+                ),
+                &labels,
+            )?,
+            conflicts: IntCounterVec::new(
+                Opts::new(
+                    "brakes_conflicts_total",
+                    "Backend writes that conflicted with a concurrent update of the key.",
+                ),
+                &labels,
+            )?,
+            latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "brakes_decision_duration_seconds",
+                    "Rate limiting decision latency.",
+                )
+                .buckets(BUCKETS.to_vec()),
+                &labels,
+            )?,
+            backend_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "brakes_backend_duration_seconds",
+                    "Time spent in backend calls per decision.",
+                )
+                .buckets(BUCKETS.to_vec()),
+                &labels,
+            )?,
+        };
+        for (i, collector) in metrics.collectors().into_iter().enumerate() {
+            if let Err(e) = registry.register(collector) {
+                // all or nothing, so that a failed call can be retried once the conflict is gone
+                for registered in metrics.collectors().into_iter().take(i) {
+                    let _ = registry.unregister(registered);
+                }
+                return Err(e);
+            }
+        }
+        Ok(metrics)
+    }
+
+    /// A listener for `RateLimiterBuilder::on_decision`, recording decisions under the `limiter` label.
+    pub fn listener(&self, limiter: &str) -> impl Fn(&Decision) + Send + Sync + 'static {
+        let metrics = self.clone();
+        let limiter = limiter.to_string();
+        move |decision| metrics.record(&limiter, decision)
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Every metric of the registry in the Prometheus text format.
+    pub fn render(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+
+    fn collectors(&self) -> [Box<dyn Collector>; 6] {
+        [
+            Box::new(self.decisions.clone()),
+            Box::new(self.fallbacks.clone()),
+            Box::new(self.retries.clone()),
+            Box::new(self.conflicts.clone()),
+            Box::new(self.latency.clone()),
+            Box::new(self.backend_latency.clone()),
+        ]
+    }
+
+    fn record(&self, limiter: &str, decision: &Decision) {
+        let labels = [limiter, decision.backend];
+        self.decisions
+            .with_label_values(&[limiter, decision.backend, decision.verdict.as_str()])
+            .inc();
+        if let Some(fallback) = decision.fallback {
+            self.fallbacks
+                .with_label_values(&[limiter, decision.backend, fallback.as_str()])
+                .inc();
+        }
+        if decision.retries > 0 {
+            self.retries
+                .with_label_values(&labels)
+                .inc_by(decision.retries as u64);
+        }
+        if decision.conflicts > 0 {
+            self.conflicts
+                .with_label_values(&labels)
+                .inc_by(decision.conflicts as u64);
+        }
+        self.latency
+            .with_label_values(&labels)
+            .observe(decision.latency.as_secs_f64());
+        self.backend_latency
+            .with_label_values(&labels)
+            .observe(decision.backend_latency.as_secs_f64());
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
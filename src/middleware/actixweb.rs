@@ -1,21 +1,29 @@
-use crate::{backend::Backend, types::LimiterType, RateLimiter};
+use super::source::{LimiterSource, Unregistered};
+use crate::{backend::Backend, registry::LimiterRegistry, types::LimiterType, RateLimiter};
 use actix_web::{
     body::{BoxBody, MessageBody},
     dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
     Error, HttpRequest, HttpResponse,
 };
 use futures_util::future::LocalBoxFuture;
-use std::future::{ready, Ready};
+use std::{
+    future::{ready, Ready},
+    sync::Arc,
+};
 
 #[derive(Clone)]
 pub struct ActixwebRateLimiter<T, B> {
-    limiter: RateLimiter<T, B>,
+    limiter: LimiterSource<T, B>,
     callback: fn(&HttpRequest) -> HttpResponse,
     key_extractor: fn(&HttpRequest) -> String,
 }
 
 impl<T: LimiterType, B: Backend> ActixwebRateLimiter<T, B> {
     pub fn new(limiter: RateLimiter<T, B>) -> Self {
+        Self::with_source(LimiterSource::Fixed(Arc::new(limiter), None))
+    }
+
+    fn with_source(limiter: LimiterSource<T, B>) -> Self {
         let default_callback = |_: &HttpRequest| HttpResponse::TooManyRequests().finish();
         let default_extractor = |req: &HttpRequest| req.peer_addr().unwrap().ip().to_string();
 
@@ -35,6 +43,29 @@ impl<T: LimiterType, B: Backend> ActixwebRateLimiter<T, B> {
         self.key_extractor = extractor;
         self
     }
+
+    /// Name the limiter is reported under (see `middleware::otel`), defaults to its registry name
+    /// or, for limiters given directly, to its algorithm's name.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.limiter = self.limiter.with_name(name);
+        self
+    }
+}
+
+impl ActixwebRateLimiter<Arc<dyn LimiterType + Send + Sync>, Arc<dyn Backend + Send + Sync>> {
+    /// Applies the limiter registered under `name` in `registry`, looked up on every request so that
+    /// replacing it takes effect immediately. Requests are let through while nothing is registered
+    /// under `name`, unless `with_unregistered` says otherwise.
+    pub fn from_registry(registry: &LimiterRegistry, name: impl Into<String>) -> Self {
+        Self::with_source(LimiterSource::named(registry, name.into()))
+    }
+
+    /// What to do with requests while nothing is registered under the name, defaults to
+    /// `Unregistered::Allow`.
+    pub fn with_unregistered(mut self, unregistered: Unregistered) -> Self {
+        self.limiter = self.limiter.with_unregistered(unregistered);
+        self
+    }
 }
 
 impl<S, B, LT, BE> Transform<S, ServiceRequest> for ActixwebRateLimiter<LT, BE>
@@ -42,8 +73,8 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
     B: MessageBody + From<BoxBody> + 'static,
-    LT: LimiterType,
-    BE: Backend,
+    LT: LimiterType + Clone + 'static,
+    BE: Backend + Clone + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -63,7 +94,7 @@ where
 
 pub struct ActixwebRateLimiterMiddleware<S, T, B> {
     service: S,
-    limiter: RateLimiter<T, B>,
+    limiter: LimiterSource<T, B>,
     callback: fn(&HttpRequest) -> HttpResponse,
     key_extractor: fn(&HttpRequest) -> String,
 }
@@ -73,8 +104,8 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
     S::Future: 'static,
     B: MessageBody + From<BoxBody> + 'static,
-    LT: LimiterType,
-    BE: Backend,
+    LT: LimiterType + Clone + 'static,
+    BE: Backend + Clone + 'static,
 {
     type Response = ServiceResponse<B>;
     type Error = Error;
@@ -83,15 +114,26 @@ where
     forward_ready!(service);
 
     fn call(&self, req: ServiceRequest) -> Self::Future {
-        if self
-            .limiter
-            .is_ratelimited(&(self.key_extractor)(req.request()))
-            .is_err()
-        {
-            let response = (self.callback)(req.request());
-            let service_response = req.into_response(response.map_into_boxed_body());
-            return Box::pin(async { Ok(service_response.map_body(|_, body| B::from(body))) });
-        }
-        Box::pin(self.service.call(req))
+        let lease = match self.limiter.get().map(|limiter| {
+            let result = limiter.admit(&(self.key_extractor)(req.request()));
+            #[cfg(feature = "otel")]
+            super::otel::record(self.limiter.name(&limiter), &result);
+            result
+        }) {
+            Some(Ok((lease, _))) => Some(lease),
+            None if self.limiter.unregistered() == Unregistered::Allow => None,
+            None | Some(Err(_)) => {
+                let response = (self.callback)(req.request());
+                let service_response = req.into_response(response.map_into_boxed_body());
+                return Box::pin(async { Ok(service_response.map_body(|_, body| B::from(body))) });
+            }
+        };
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let response = fut.await;
+            // held until the response is ready for concurrency limiters
+            drop(lease);
+            response
+        })
     }
 }
@@ -4,3 +4,12 @@ pub mod actixweb;
 #[cfg(feature = "tower")]
 #[cfg_attr(docsrs, doc(cfg(feature = "tower")))]
 pub mod tower;
+
+#[cfg(all(feature = "otel", any(feature = "actixweb", feature = "tower")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "otel")))]
+pub mod otel;
+#[cfg(any(feature = "actixweb", feature = "tower"))]
+mod source;
+
+#[cfg(any(feature = "actixweb", feature = "tower"))]
+pub use source::Unregistered;
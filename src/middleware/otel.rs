@@ -0,0 +1,77 @@
+//! OpenTelemetry instrumentation of the middlewares (`otel` feature).
+//!
+//! Each request the middlewares decide on gets the `ratelimit.*` attributes below on the current
+//! span, and is counted in the `brakes.middleware.requests` counter (by limiter and decision). The
+//! requests keys still admit are recorded in the `brakes.middleware.remaining` histogram.
+//!
+//! Metrics go through the global meter provider (`opentelemetry::global::set_meter_provider`), which
+//! should be installed before the first request. Requests answered while nothing is registered under
+//! a limiter's name (see `LimiterRegistry`) aren't recorded.
+
+use crate::types::RateLimiterError;
+use opentelemetry::{
+    global,
+    metrics::{Counter, Histogram},
+    trace::get_active_span,
+    KeyValue,
+};
+use std::sync::OnceLock;
+
+/// Name of the limiter: the one set with the middleware's `with_name`, else its registry name (see
+/// `LimiterRegistry`) or, for limiters given directly, its algorithm's name.
+pub const LIMITER: &str = "ratelimit.limiter";
+/// `allowed`, `limited` or `failed` (any other error, ex: a backend failure with `RetryStrategy::Deny`).
+pub const DECISION: &str = "ratelimit.decision";
+/// Requests the key still admits, see `LimiterType::remaining`. Left out if the limiter can't tell.
+pub const REMAINING: &str = "ratelimit.remaining";
+
+struct Instruments {
+    requests: Counter<u64>,
+    remaining: Histogram<u64>,
+}
+
+// created on the first request, once the service has installed its meter provider
+fn instruments() -> &'static Instruments {
+    static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+    INSTRUMENTS.get_or_init(|| {
+        let meter = global::meter("brakes");
+        Instruments {
+            requests: meter
+                .u64_counter("brakes.middleware.requests")
+                .with_description("Requests seen by the rate limiting middlewares, by decision.")
+                .with_unit("{request}")
+                .build(),
+            remaining: meter
+                .u64_histogram("brakes.middleware.remaining")
+                .with_description("Requests the key still admitted after each decision.")
+                .with_unit("{request}")
+                .build(),
+        }
+    })
+}
+
+/// Records a decision, ex: the result of `RateLimiter::admit`, on the current span and in the
+/// middleware metrics.
+pub(super) fn record<T>(limiter: &str, result: &Result<(T, Option<u32>), RateLimiterError>) {
+    let (decision, remaining) = match result {
+        Ok((_, remaining)) => ("allowed", *remaining),
+        Err(RateLimiterError::RateExceeded) => ("limited", Some(0)),
+        Err(_) => ("failed", None),
+    };
+    let limiter = KeyValue::new(LIMITER, limiter.to_string());
+
+    get_active_span(|span| {
+        span.set_attribute(limiter.clone());
+        span.set_attribute(KeyValue::new(DECISION, decision));
+        if let Some(remaining) = remaining {
+            span.set_attribute(KeyValue::new(REMAINING, remaining as i64));
+        }
+    });
+    let instruments = instruments();
+    instruments
+        .requests
+        .add(1, &[limiter.clone(), KeyValue::new(DECISION, decision)]);
+    if let Some(remaining) = remaining {
+        instruments.remaining.record(remaining as u64, &[limiter]);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{
+    backend::Backend, registry::LimiterRegistry, types::LimiterType, DynRateLimiter, RateLimiter,
+};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// What middlewares created with `from_registry` do with requests while nothing is registered under
+/// their limiter's name, set with `with_unregistered`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unregistered {
+    /// Let requests through.
+    #[default]
+    Allow,
+    /// Answer requests with the middleware's callback, as if they were rate limited.
+    Deny,
+}
+
+/// The limiter a middleware applies: either its own, with the name set by `with_name` if any, or the
+/// one registered under a name, looked up on every request.
+pub(super) enum LimiterSource<T, B> {
+    Fixed(Arc<RateLimiter<T, B>>, Option<Arc<str>>),
+    Named(Arc<str>, Arc<Lookup<T, B>>, Unregistered),
+}
+
+type Lookup<T, B> = dyn Fn() -> Option<Arc<RateLimiter<T, B>>> + Send + Sync;
+
+impl<T, B> LimiterSource<T, B> {
+    /// `None` if nothing is registered under the name, see `unregistered`.
+    pub(super) fn get(&self) -> Option<Arc<RateLimiter<T, B>>> {
+        match self {
+            LimiterSource::Fixed(limiter, _) => Some(limiter.clone()),
+            LimiterSource::Named(_, lookup, _) => lookup(),
+        }
+    }
+
+    /// What to do with requests while nothing is registered under the name.
+    pub(super) fn unregistered(&self) -> Unregistered {
+        match self {
+            LimiterSource::Fixed(..) => Unregistered::Allow,
+            LimiterSource::Named(_, _, unregistered) => *unregistered,
+        }
+    }
+
+    pub(super) fn with_unregistered(self, unregistered: Unregistered) -> Self {
+        match self {
+            LimiterSource::Named(name, lookup, _) => {
+                LimiterSource::Named(name, lookup, unregistered)
+            }
+            fixed => fixed,
+        }
+    }
+
+    pub(super) fn with_name(self, name: &str) -> Self {
+        match self {
+            LimiterSource::Fixed(limiter, _) => {
+                LimiterSource::Fixed(limiter, Some(Arc::from(name)))
+            }
+            LimiterSource::Named(_, lookup, unregistered) => {
+                LimiterSource::Named(Arc::from(name), lookup, unregistered)
+            }
+        }
+    }
+
+    /// The name the limiter is reported under: the one set with `with_name`, its registry name, or
+    /// its algorithm's.
+    #[cfg(feature = "otel")]
+    pub(super) fn name<'a>(&'a self, limiter: &'a RateLimiter<T, B>) -> &'a str
+    where
+        T: LimiterType,
+    {
+        match self {
+            LimiterSource::Fixed(_, Some(name)) => name,
+            LimiterSource::Fixed(_, None) => limiter.limiter.name(),
+            LimiterSource::Named(name, _, _) => name,
+        }
+    }
+}
+
+impl LimiterSource<Arc<dyn LimiterType + Send + Sync>, Arc<dyn Backend + Send + Sync>> {
+    pub(super) fn named(registry: &LimiterRegistry, name: String) -> Self {
+        let registry = registry.clone();
+        // warned once until a limiter is registered under the name
+        let warned = AtomicBool::new(false);
+        LimiterSource::Named(
+            Arc::from(name.as_str()),
+            Arc::new(move || {
+                let limiter: Option<Arc<DynRateLimiter>> = registry.get(&name);
+                if limiter.is_some() {
+                    warned.store(false, Ordering::Relaxed);
+                } else if !warned.swap(true, Ordering::Relaxed) {
+                    log::warn!("no limiter registered under {:?}", name);
+                }
+                limiter
+            }),
+            Unregistered::Allow,
+        )
+    }
+}
+
+impl<T, B> Clone for LimiterSource<T, B> {
+    fn clone(&self) -> Self {
+        match self {
+            LimiterSource::Fixed(limiter, name) => {
+                LimiterSource::Fixed(limiter.clone(), name.clone())
+            }
+            LimiterSource::Named(name, lookup, unregistered) => {
+                LimiterSource::Named(name.clone(), lookup.clone(), *unregistered)
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug, B: fmt::Debug> fmt::Debug for LimiterSource<T, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimiterSource::Fixed(limiter, name) => {
+                f.debug_tuple("Fixed").field(limiter).field(name).finish()
+            }
+            LimiterSource::Named(name, _, unregistered) => f
+                .debug_tuple("Named")
+                .field(name)
+                .field(unregistered)
+                .finish(),
+        }
+    }
+}
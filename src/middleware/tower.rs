@@ -1,13 +1,29 @@
-use crate::{backend::Backend, types::LimiterType, RateLimiter};
-use futures::future::{ready, Either, Ready};
+use super::source::{LimiterSource, Unregistered};
+use crate::{
+    backend::Backend,
+    registry::LimiterRegistry,
+    types::{LimiterType, Outcome},
+    Lease, RateLimiter,
+};
+use futures::{
+    future::{ready, Either, Ready},
+    ready,
+};
 use http::{Request, Response, StatusCode};
-use std::task::{Context, Poll};
+use pin_project_lite::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
 use tower::{Layer, Service};
 
 #[derive(Debug, Clone)]
 pub struct TowerRateLimiter<S, T, B, F, K> {
     inner: S,
-    limiter: RateLimiter<T, B>,
+    limiter: LimiterSource<T, B>,
     callback: F,
     key_extractor: K,
 }
@@ -16,47 +32,118 @@ impl<S, T: LimiterType, B: Backend, F, K> TowerRateLimiter<S, T, B, F, K> {
     pub fn new(inner: S, limiter: RateLimiter<T, B>, callback: F, key_extractor: K) -> Self {
         TowerRateLimiter {
             inner,
-            limiter,
+            limiter: LimiterSource::Fixed(Arc::new(limiter), None),
             callback,
             key_extractor,
         }
     }
+
+    /// Name the limiter is reported under (see `middleware::otel`), defaults to its registry name
+    /// or, for limiters given directly, to its algorithm's name.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.limiter = self.limiter.with_name(name);
+        self
+    }
 }
 
 impl<S, ReqBody, ResBody, F, T, B, K> Service<Request<ReqBody>> for TowerRateLimiter<S, T, B, F, K>
 where
     S: Service<Request<ReqBody>, Response = Response<ResBody>>,
-    T: LimiterType,
-    B: Backend,
+    T: LimiterType + Clone,
+    B: Backend + Clone,
     ResBody: Default,
     F: Fn(Request<ReqBody>) -> Response<ResBody>,
     K: Fn(&Request<ReqBody>) -> String,
 {
     type Response = S::Response;
     type Error = S::Error;
-    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+    type Future =
+        Either<Ready<Result<Self::Response, Self::Error>>, ResponseFuture<S::Future, T, B>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
     }
 
     fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
-        if self
-            .limiter
-            .is_ratelimited(&(self.key_extractor)(&request))
-            .is_err()
-        {
-            let response = (self.callback)(request);
-            Either::Left(ready(Ok(response)))
-        } else {
-            Either::Right(self.inner.call(request))
+        let Some(limiter) = self.limiter.get() else {
+            if self.limiter.unregistered() == Unregistered::Deny {
+                return Either::Left(ready(Ok((self.callback)(request))));
+            }
+            return Either::Right(ResponseFuture {
+                inner: self.inner.call(request),
+                lease: None,
+                feedback: None,
+            });
+        };
+        let key = (self.key_extractor)(&request);
+        let result = limiter.admit(&key);
+        #[cfg(feature = "otel")]
+        super::otel::record(self.limiter.name(&limiter), &result);
+        match result {
+            Ok((lease, _)) => {
+                let feedback = limiter
+                    .wants_feedback()
+                    .then(|| (limiter.clone(), key, Instant::now()));
+                Either::Right(ResponseFuture {
+                    inner: self.inner.call(request),
+                    lease: Some(lease),
+                    feedback,
+                })
+            }
+            Err(_) => {
+                let response = (self.callback)(request);
+                Either::Left(ready(Ok(response)))
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// Response future of `TowerRateLimiter`. Holds on to the request's `Lease` until the inner
+    /// service responds, and reports the response to limiters that adapt to feedback.
+    ///
+    /// Server errors, `429 Too Many Requests` and service errors are reported as `Outcome::Failure`,
+    /// any other response as `Outcome::Latency`.
+    pub struct ResponseFuture<F, T: LimiterType, B: Backend> {
+        #[pin]
+        inner: F,
+        lease: Option<Lease<T, B>>,
+        feedback: Option<(Arc<RateLimiter<T, B>>, String, Instant)>,
+    }
+}
+
+impl<F, ResBody, E, T, B> Future for ResponseFuture<F, T, B>
+where
+    F: Future<Output = Result<Response<ResBody>, E>>,
+    T: LimiterType,
+    B: Backend,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let output = ready!(this.inner.poll(cx));
+        this.lease.take();
+        if let Some((limiter, key, start)) = this.feedback.take() {
+            let outcome = match &output {
+                Ok(response)
+                    if response.status().is_server_error()
+                        || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    Outcome::Failure
+                }
+                Ok(_) => Outcome::Latency(start.elapsed()),
+                Err(_) => Outcome::Failure,
+            };
+            let _ = limiter.report(&key, outcome);
         }
+        Poll::Ready(output)
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct TowerRateLimiterLayer<T, B, F, K> {
-    limiter: RateLimiter<T, B>,
+    limiter: LimiterSource<T, B>,
     callback: F,
     key_extractor: K,
 }
@@ -64,11 +151,45 @@ pub struct TowerRateLimiterLayer<T, B, F, K> {
 impl<T: LimiterType, B: Backend, F, K> TowerRateLimiterLayer<T, B, F, K> {
     pub fn new(limiter: RateLimiter<T, B>, callback: F, key_extractor: K) -> Self {
         TowerRateLimiterLayer {
-            limiter,
+            limiter: LimiterSource::Fixed(Arc::new(limiter), None),
             callback,
             key_extractor,
         }
     }
+
+    /// Name the limiter is reported under (see `middleware::otel`), defaults to its registry name
+    /// or, for limiters given directly, to its algorithm's name.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.limiter = self.limiter.with_name(name);
+        self
+    }
+}
+
+impl<F, K>
+    TowerRateLimiterLayer<Arc<dyn LimiterType + Send + Sync>, Arc<dyn Backend + Send + Sync>, F, K>
+{
+    /// Applies the limiter registered under `name` in `registry`, looked up on every request so that
+    /// replacing it takes effect immediately. Requests are let through while nothing is registered
+    /// under `name`, unless `with_unregistered` says otherwise.
+    pub fn from_registry(
+        registry: &LimiterRegistry,
+        name: impl Into<String>,
+        callback: F,
+        key_extractor: K,
+    ) -> Self {
+        TowerRateLimiterLayer {
+            limiter: LimiterSource::named(registry, name.into()),
+            callback,
+            key_extractor,
+        }
+    }
+
+    /// What to do with requests while nothing is registered under the name, defaults to
+    /// `Unregistered::Allow`.
+    pub fn with_unregistered(mut self, unregistered: Unregistered) -> Self {
+        self.limiter = self.limiter.with_unregistered(unregistered);
+        self
+    }
 }
 
 pub fn default_callback<T, S: Default>(_: Request<T>) -> Response<S> {
@@ -83,7 +204,7 @@ impl<T: LimiterType, B: Backend, ReqBody, ResBody: Default, K>
 {
     pub fn default(limiter: RateLimiter<T, B>, key_extractor: K) -> Self {
         TowerRateLimiterLayer {
-            limiter,
+            limiter: LimiterSource::Fixed(Arc::new(limiter), None),
             callback: default_callback,
             key_extractor,
         }
@@ -92,17 +213,17 @@ impl<T: LimiterType, B: Backend, ReqBody, ResBody: Default, K>
 
 impl<S, T, B, F: Clone, K: Clone> Layer<S> for TowerRateLimiterLayer<T, B, F, K>
 where
-    T: LimiterType,
-    B: Backend,
+    T: LimiterType + Clone,
+    B: Backend + Clone,
 {
     type Service = TowerRateLimiter<S, T, B, F, K>;
 
     fn layer(&self, service: S) -> Self::Service {
-        TowerRateLimiter::new(
-            service,
-            self.limiter.clone(),
-            self.callback.clone(),
-            self.key_extractor.clone(),
-        )
+        TowerRateLimiter {
+            inner: service,
+            limiter: self.limiter.clone(),
+            callback: self.callback.clone(),
+            key_extractor: self.key_extractor.clone(),
+        }
     }
 }
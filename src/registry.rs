@@ -0,0 +1,119 @@
+//! Named limiters that can be replaced while the service is running.
+//!
+//! A `LimiterRegistry` maps names to `DynRateLimiter`s. Lookups return the limiter registered at that
+//! moment, so swapping an entry (or reloading all of them from a configuration file) takes effect on
+//! the next request without restarting anything. Middlewares can be built from a registry and a name,
+//! see `ActixwebRateLimiter::from_registry` and `TowerRateLimiterLayer::from_registry`. They let
+//! requests through while nothing is registered under their name, or deny them with
+//! `with_unregistered(Unregistered::Deny)`, and log a warning once until a limiter is registered.
+//!
+//! Limiters sharing a backend also share its keys, so the same key in two limiters refers to the same
+//! cache entry. Give each of them a namespace (`namespace` in `RateLimiterConfig`) when this matters.
+
+use crate::{
+    backend::Backend,
+    config::{ConfigError, RateLimiterConfig},
+    DynRateLimiter,
+};
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+/// A set of named limiters, cheap to clone and shared between clones.
+#[derive(Clone, Default)]
+pub struct LimiterRegistry {
+    limiters: Arc<RwLock<HashMap<String, Arc<DynRateLimiter>>>>,
+}
+
+impl LimiterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the limiter currently registered under `name`.
+    pub fn get(&self, name: &str) -> Option<Arc<DynRateLimiter>> {
+        self.read().get(name).cloned()
+    }
+
+    /// Registers `limiter` under `name`, returning the one it replaces. Requests already holding the
+    /// previous limiter (ex: for a lease) finish with it.
+    pub fn insert(
+        &self,
+        name: impl Into<String>,
+        limiter: DynRateLimiter,
+    ) -> Option<Arc<DynRateLimiter>> {
+        self.write().insert(name.into(), Arc::new(limiter))
+    }
+
+    pub fn remove(&self, name: &str) -> Option<Arc<DynRateLimiter>> {
+        self.write().remove(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.read().keys().cloned().collect()
+    }
+
+    /// Replaces every limiter at once: a lookup sees either the previous set or the new one.
+    pub fn replace_all(&self, limiters: HashMap<String, DynRateLimiter>) {
+        let limiters = limiters
+            .into_iter()
+            .map(|(name, limiter)| (name, Arc::new(limiter)))
+            .collect();
+        *self.write() = limiters;
+    }
+
+    /// Builds every limiter in `configs`, each on its configured backend, and replaces the registered
+    /// ones with them. If any of them fails to build, the registry is left untouched.
+    ///
+    /// The backends are created anew, so limiters using the `memory` backend start from empty counters,
+    /// see `reload_with_backend` to keep them.
+    pub fn reload(&self, configs: &HashMap<String, RateLimiterConfig>) -> Result<(), ConfigError> {
+        let limiters = configs
+            .iter()
+            .map(|(name, config)| Ok((name.clone(), config.build()?.into_dyn())))
+            .collect::<Result<_, ConfigError>>()?;
+        self.replace_all(limiters);
+        Ok(())
+    }
+
+    /// Same as `reload`, but every limiter uses `backend` and the backends in `configs` are ignored.
+    pub fn reload_with_backend<B>(
+        &self,
+        configs: &HashMap<String, RateLimiterConfig>,
+        backend: B,
+    ) -> Result<(), ConfigError>
+    where
+        B: Backend + Clone + Send + Sync + 'static,
+    {
+        let limiters = configs
+            .iter()
+            .map(|(name, config)| {
+                let limiter = config.build_with_backend(backend.clone())?;
+                Ok((name.clone(), limiter.into_dyn()))
+            })
+            .collect::<Result<_, ConfigError>>()?;
+        self.replace_all(limiters);
+        Ok(())
+    }
+
+    // a panic while holding the lock can't leave the map half updated
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<DynRateLimiter>>> {
+        self.limiters.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, Arc<DynRateLimiter>>> {
+        self.limiters
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl fmt::Debug for LimiterRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LimiterRegistry")
+            .field("limiters", &self.names())
+            .finish()
+    }
+}
@@ -0,0 +1,333 @@
+//! `Codec::Compact`, see the storage module documentation for the layout.
+
+use super::{malformed, Envelope, EnvelopeRef, FORMAT_VERSION};
+use crate::types::{
+    adaptive::AdaptiveInstance, concurrency::ConcurrencyInstance,
+    fixed_window::FixedWindowInstance, leaky_bucket::LeakyBucketInstance,
+    sliding_window::SlidingWindowInstance, sliding_window_log::SlidingWindowLogInstance,
+    token_bucket::TokenBucketInstance, LimiterInstance, RateLimiterError,
+};
+
+// high nibble of the first byte, bare instances start with their variant index instead
+const HEADER: u8 = 0xc0;
+
+// built-in limiters are stored by their index in this list, plus one
+const LIMITERS: [&str; 8] = [
+    "fixed_window",
+    "calendar_window",
+    "sliding_window_counter",
+    "sliding_window_log",
+    "token_bucket",
+    "leaky_bucket",
+    "concurrency",
+    "adaptive",
+];
+
+pub(super) fn encode(envelope: &EnvelopeRef) -> Result<Vec<u8>, RateLimiterError> {
+    let mut w = Writer(Vec::with_capacity(24));
+    w.0.push(HEADER | envelope.version as u8);
+    match LIMITERS.iter().position(|l| *l == envelope.limiter) {
+        Some(i) => w.varint(i as u64 + 1),
+        None => {
+            w.varint(0);
+            w.bytes(envelope.limiter.as_bytes());
+        }
+    }
+    w.0.extend((envelope.fingerprint as u32).to_le_bytes());
+    w.varint(envelope.limit.map_or(0, |l| l as u64 + 1));
+
+    match envelope.instance {
+        LimiterInstance::FixedWindowInstance(i) => {
+            w.varint(0);
+            w.fixed_window(i)?;
+        }
+        LimiterInstance::SlidingWindowInstance(i) => {
+            w.varint(1);
+            w.fixed_window(&i.current)?;
+            // the previous window ends where the current one starts
+            w.signed(i.current.window_start as i128 - i.previous.window_start as i128)?;
+            w.varint(i.previous.count as u64);
+        }
+        LimiterInstance::TokenBucketInstance(i) => {
+            w.varint(2);
+            w.varint(i.tokens);
+            w.timestamp(i.last_access)?;
+        }
+        LimiterInstance::LeakyBucketInstance(i) => {
+            w.varint(3);
+            w.varint(i.processed as u64);
+            w.timestamp(i.last_leaked)?;
+        }
+        LimiterInstance::SlidingWindowLogInstance(i) => {
+            w.varint(4);
+            w.varint(i.entries.len() as u64);
+            let mut previous = 0;
+            for (ts, count) in &i.entries {
+                w.signed(*ts as i128 - previous as i128)?;
+                w.varint(*count as u64);
+                previous = *ts;
+            }
+        }
+        LimiterInstance::ConcurrencyInstance(i) => {
+            w.varint(5);
+            w.varint(i.leases.len() as u64);
+            let mut previous = 0;
+            for (id, expires_at) in &i.leases {
+                w.0.extend(id.to_le_bytes());
+                w.signed(*expires_at as i128 - previous as i128)?;
+                previous = *expires_at;
+            }
+        }
+        LimiterInstance::AdaptiveInstance(i) => {
+            w.varint(6);
+            w.varint(i.limit as u64);
+            w.varint(i.successes as u64);
+            w.fixed_window(&i.window)?;
+        }
+        LimiterInstance::Custom { type_id, bytes } => {
+            w.varint(7);
+            w.bytes(type_id.as_bytes());
+            w.bytes(bytes);
+        }
+    }
+    Ok(w.0)
+}
+
+pub(super) fn decode(bytes: &[u8]) -> Result<Envelope, RateLimiterError> {
+    let mut r = Reader(bytes);
+    let header = r.byte()?;
+    if header & 0xf0 != HEADER {
+        return Err(malformed("not an envelope"));
+    }
+    // the compact layout always stored the limit, versions 1 and 2 are read the same way
+    let version = (header & 0x0f) as u16;
+    if version > FORMAT_VERSION {
+        return Err(malformed(format!("unknown format version {}", version)));
+    }
+    let limiter = match r.varint()? {
+        0 => String::from_utf8(r.bytes()?.to_vec()).map_err(malformed)?,
+        i => LIMITERS
+            .get(i as usize - 1)
+            .ok_or_else(|| malformed(format!("unknown limiter {}", i)))?
+            .to_string(),
+    };
+    let fingerprint = u32::from_le_bytes(r.array()?) as u64;
+    let limit = match r.varint()? {
+        0 => None,
+        l => Some(u32::try_from(l - 1).map_err(malformed)?),
+    };
+
+    let instance = match r.varint()? {
+        0 => LimiterInstance::FixedWindowInstance(r.fixed_window()?),
+        1 => {
+            let current = r.fixed_window()?;
+            let previous_start = r.relative(current.window_start, true)?;
+            let previous = FixedWindowInstance::new(previous_start, r.varint32()?);
+            LimiterInstance::SlidingWindowInstance(SlidingWindowInstance { current, previous })
+        }
+        2 => LimiterInstance::TokenBucketInstance(TokenBucketInstance {
+            tokens: r.varint()?,
+            last_access: r.varint()? as u128,
+        }),
+        3 => LimiterInstance::LeakyBucketInstance(LeakyBucketInstance {
+            processed: r.varint32()?,
+            last_leaked: r.varint()? as u128,
+        }),
+        4 => {
+            let mut entries = Vec::new();
+            let mut previous = 0;
+            for _ in 0..r.varint()? {
+                previous = r.relative(previous, false)?;
+                entries.push((previous, r.varint32()?));
+            }
+            LimiterInstance::SlidingWindowLogInstance(SlidingWindowLogInstance { entries })
+        }
+        5 => {
+            let mut leases = Vec::new();
+            let mut previous = 0;
+            for _ in 0..r.varint()? {
+                let id = u64::from_le_bytes(r.array()?);
+                previous = r.relative(previous, false)?;
+                leases.push((id, previous));
+            }
+            LimiterInstance::ConcurrencyInstance(ConcurrencyInstance { leases })
+        }
+        6 => LimiterInstance::AdaptiveInstance(AdaptiveInstance {
+            limit: r.varint32()?,
+            successes: r.varint32()?,
+            window: r.fixed_window()?,
+        }),
+        7 => LimiterInstance::Custom {
+            type_id: String::from_utf8(r.bytes()?.to_vec()).map_err(malformed)?,
+            bytes: r.bytes()?.to_vec(),
+        },
+        i => return Err(malformed(format!("unknown instance type {}", i))),
+    };
+    if !r.0.is_empty() {
+        return Err(malformed("trailing bytes"));
+    }
+
+    Ok(Envelope {
+        version,
+        limiter,
+        fingerprint,
+        limit,
+        instance,
+    })
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    // LEB128
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    // zigzag, so that small negative offsets stay small
+    fn signed(&mut self, v: i128) -> Result<(), RateLimiterError> {
+        let v = i64::try_from(v).map_err(malformed)?;
+        self.varint(((v << 1) ^ (v >> 63)) as u64);
+        Ok(())
+    }
+
+    // milliseconds fit in a u64 for the next 500 million years
+    fn timestamp(&mut self, ts: u128) -> Result<(), RateLimiterError> {
+        self.varint(u64::try_from(ts).map_err(malformed)?);
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.varint(bytes.len() as u64);
+        self.0.extend_from_slice(bytes);
+    }
+
+    fn fixed_window(&mut self, i: &FixedWindowInstance) -> Result<(), RateLimiterError> {
+        self.timestamp(i.window_start)?;
+        self.varint(i.count as u64);
+        Ok(())
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, RateLimiterError> {
+        let (first, rest) = self
+            .0
+            .split_first()
+            .ok_or_else(|| malformed("truncated value"))?;
+        self.0 = rest;
+        Ok(*first)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], RateLimiterError> {
+        if self.0.len() < N {
+            return Err(malformed("truncated value"));
+        }
+        let (array, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(array.try_into().unwrap())
+    }
+
+    fn varint(&mut self) -> Result<u64, RateLimiterError> {
+        let mut v = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(malformed("varint too long"))
+    }
+
+    // a timestamp stored as an offset from `base`, subtracted if `before`
+    fn relative(&mut self, base: u128, before: bool) -> Result<u128, RateLimiterError> {
+        let v = self.varint()?;
+        let offset = ((v >> 1) as i64 ^ -((v & 1) as i64)) as i128;
+        let ts = if before {
+            base as i128 - offset
+        } else {
+            base as i128 + offset
+        };
+        u128::try_from(ts).map_err(malformed)
+    }
+
+    fn varint32(&mut self) -> Result<u32, RateLimiterError> {
+        u32::try_from(self.varint()?).map_err(malformed)
+    }
+
+    fn bytes(&mut self) -> Result<&[u8], RateLimiterError> {
+        let len = self.varint()? as usize;
+        if self.0.len() < len {
+            return Err(malformed("truncated value"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn fixed_window(&mut self) -> Result<FixedWindowInstance, RateLimiterError> {
+        let window_start = self.varint()? as u128;
+        Ok(FixedWindowInstance::new(window_start, self.varint32()?))
+    }
+}
+
+#[test]
+fn compact() {
+    let now = 1_704_067_200_000u128;
+    let instances = [
+        LimiterInstance::FixedWindowInstance(FixedWindowInstance::new(now, 7)),
+        LimiterInstance::SlidingWindowInstance(SlidingWindowInstance {
+            current: FixedWindowInstance::new(now, 3),
+            previous: FixedWindowInstance::new(now - 60_000, 9),
+        }),
+        LimiterInstance::TokenBucketInstance(TokenBucketInstance::new(now, 5)),
+        LimiterInstance::SlidingWindowLogInstance(SlidingWindowLogInstance {
+            entries: vec![(now, 2), (now + 15, 1)],
+        }),
+        LimiterInstance::ConcurrencyInstance(ConcurrencyInstance {
+            leases: vec![(u64::MAX, now + 30_000), (1, now)],
+        }),
+        LimiterInstance::Custom {
+            type_id: "lifetime".to_string(),
+            bytes: vec![1, 2, 3],
+        },
+    ];
+
+    for instance in &instances {
+        let bytes = encode(&EnvelopeRef {
+            version: FORMAT_VERSION,
+            limiter: "a_custom_limiter",
+            fingerprint: u64::MAX,
+            limit: Some(100),
+            instance,
+        })
+        .unwrap();
+        let envelope = decode(&bytes).unwrap();
+        assert_eq!(envelope.limiter, "a_custom_limiter");
+        assert_eq!(envelope.fingerprint, u32::MAX as u64);
+        assert_eq!(envelope.limit, Some(100));
+        assert_eq!(
+            format!("{:?}", envelope.instance),
+            format!("{:?}", instance)
+        );
+    }
+
+    // header, limiter, fingerprint, limit, tag, window start and count
+    let bytes = encode(&EnvelopeRef {
+        version: FORMAT_VERSION,
+        limiter: "fixed_window",
+        fingerprint: 0,
+        limit: Some(100),
+        instance: &instances[0],
+    })
+    .unwrap();
+    assert_eq!(bytes.len(), 1 + 1 + 4 + 1 + 1 + 6 + 1);
+    assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+}
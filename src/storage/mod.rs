@@ -0,0 +1,440 @@
+//! How instances are stored in the backend.
+//!
+//! Every value is an [`Envelope`]: the instance, tagged with the format version, the name of the
+//! limiter that wrote it and a fingerprint of its configuration. Values written before envelopes
+//! existed (a bare `LimiterInstance`) are still read, including token buckets from when their tokens
+//! were an `f32`: they are converted to the current fixed-point count.
+//!
+//! When a key was last written by a different limiter, for example while rolling out a switch from
+//! `FixedWindow` to `TokenBucket`, a migration registered with `RateLimiterBuilder::with_migration`
+//! converts its instance instead of discarding it. When the same limiter was reconfigured instead,
+//! the `ConfigChangePolicy` set with `RateLimiterBuilder::with_config_change_policy` decides what
+//! happens to the usage recorded under the previous configuration.
+//!
+//! # Codecs
+//!
+//! The [`Codec`] set with `RateLimiterBuilder::with_codec` decides how envelopes are encoded. Every
+//! process sharing a backend has to use the same one:
+//! - `Bincode` (the default): the envelope encoded with `bincode`'s default options, after the two
+//!   bytes `b"bk"`.
+//! - `Compact`: a hand-written layout (see [below](self#compact-layout)) for large deployments,
+//!   about a quarter of the size of `Bincode`.
+//! - `Json` (`json` feature) and `MessagePack` (`msgpack` feature, as a map with named fields) are
+//!   meant for services written in other languages.
+//!
+//! # Schema
+//!
+//! Envelopes have the following fields, in this order:
+//!
+//! | field         | type          |                                                          |
+//! |---------------|---------------|----------------------------------------------------------|
+//! | `version`     | u16           | [`FORMAT_VERSION`]                                       |
+//! | `limiter`     | string        | `LimiterType::name` of the limiter that wrote the value   |
+//! | `fingerprint` | u64           | `LimiterType::fingerprint` of that limiter               |
+//! | `limit`       | optional u32  | `LimiterType::limit` of that limiter, since version 2    |
+//! | `instance`    | instance      | one of the variants below                                |
+//!
+//! The instance is a tagged union, in JSON `{"<variant>": {<fields>}}`. Timestamps are
+//! milliseconds since the unix epoch.
+//!
+//! JSON writes u64 and u128 fields (the fingerprint, timestamps, tokens and lease ids) as decimal
+//! strings, since most JSON readers parse numbers as doubles and lose precision past 2^53. Numbers
+//! are still read, as long as they are exact.
+//!
+//! | limiter                                    | variant                    | fields                                                                          |
+//! |--------------------------------------------|----------------------------|---------------------------------------------------------------------------------|
+//! | `fixed_window`, `calendar_window`          | `FixedWindowInstance`      | `window_start`: u128, `count`: u32                                              |
+//! | `sliding_window_counter`                   | `SlidingWindowInstance`    | `current`, `previous`: `FixedWindowInstance` fields                              |
+//! | `token_bucket`                             | `TokenBucketInstance`      | `tokens`: u64 (in `1 / TOKEN_SCALE` of a token), `last_access`: u128            |
+//! | `leaky_bucket`                             | `LeakyBucketInstance`      | `processed`: u32, `last_leaked`: u128                                           |
+//! | `sliding_window_log`                       | `SlidingWindowLogInstance` | `entries`: list of `[timestamp: u128, count: u32]`, oldest first                |
+//! | `concurrency`                              | `ConcurrencyInstance`      | `leases`: list of `[lease id: u64, expires_at: u128]`                           |
+//! | `adaptive`                                 | `AdaptiveInstance`         | `limit`: u32, `successes`: u32, `window`: `FixedWindowInstance` fields           |
+//! | user-defined                               | `Custom`                   | `type_id`: string, `bytes`: the instance encoded with `bincode`                 |
+//!
+//! # Compact layout
+//!
+//! Integers are LEB128 varints unless stated otherwise, signed ones are zigzag encoded first.
+//! Timestamps are stored as u64 milliseconds, lists of timestamps as offsets from the previous one.
+//!
+//! | field         |                                                                                        |
+//! |---------------|----------------------------------------------------------------------------------------|
+//! | header        | one byte, `0xc0 \| version`                                                            |
+//! | `limiter`     | 1 `fixed_window`, 2 `calendar_window`, 3 `sliding_window_counter`, 4 `sliding_window_log`, 5 `token_bucket`, 6 `leaky_bucket`, 7 `concurrency`, 8 `adaptive`, or 0 followed by the name's length and bytes |
+//! | `fingerprint` | low 32 bits, 4 bytes little endian                                                     |
+//! | `limit`       | 0 if unknown, the limit plus one otherwise                                             |
+//! | `instance`    | the variant's index in the table above (0 to 7), then its fields in the order listed; the previous window of a `SlidingWindowInstance` is stored as its (signed) distance before the current one, and lease ids as 8 bytes little endian |
+//!
+//! Bytes per key after a single request, and at 10 million keys, as measured by the
+//! `storage_size` benchmark:
+//!
+//! | limiter                  | `Bincode`        | `Compact`       | `Json`            | `MessagePack`     |
+//! |--------------------------|------------------|-----------------|-------------------|-------------------|
+//! | `fixed_window`           | 61 B (610 MB)    | 15 B (150 MB)   | 164 B (1.64 GB)   | 128 B (1.28 GB)   |
+//! | `sliding_window_counter` | 91 B (910 MB)    | 19 B (190 MB)   | 242 B (2.42 GB)   | 197 B (1.97 GB)   |
+//! | `sliding_window_log`     | 75 B (750 MB)    | 16 B (160 MB)   | 167 B (1.67 GB)   | 130 B (1.30 GB)   |
+//! | `token_bucket`           | 65 B (650 MB)    | 18 B (180 MB)   | 173 B (1.73 GB)   | 132 B (1.32 GB)   |
+//! | `leaky_bucket`           | 61 B (610 MB)    | 15 B (150 MB)   | 167 B (1.67 GB)   | 131 B (1.31 GB)   |
+//!
+//! Each further `sliding_window_log` entry takes 2 to 4 bytes in `Compact`.
+
+mod compact;
+pub(crate) mod wide;
+
+use crate::types::{
+    fixed_window::FixedWindowInstance,
+    token_bucket::{TokenBucketInstance, TOKEN_SCALE},
+    LimiterInstance, LimiterType, RateLimiterError, SerializableInstance,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp, fmt,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Version of the envelope format written by this release. Version 1 envelopes, without a `limit`,
+/// are still read.
+pub const FORMAT_VERSION: u16 = 2;
+
+// leads every binary envelope, bare instances start with their variant index instead
+const MAGIC: [u8; 2] = *b"bk";
+
+/// How envelopes are encoded in the backend, see the [module documentation](self#codecs).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Codec {
+    #[default]
+    Bincode,
+    Compact,
+    #[cfg(feature = "json")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "json")))]
+    Json,
+    #[cfg(feature = "msgpack")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "msgpack")))]
+    MessagePack,
+}
+
+impl Codec {
+    fn encode(&self, envelope: &EnvelopeRef) -> Result<Vec<u8>, RateLimiterError> {
+        match self {
+            Codec::Bincode => {
+                let mut bytes = MAGIC.to_vec();
+                bincode::serialize_into(&mut bytes, envelope)
+                    .map_err(RateLimiterError::MalformedValue)?;
+                Ok(bytes)
+            }
+            Codec::Compact => compact::encode(envelope),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::to_vec(envelope).map_err(malformed),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::to_vec_named(envelope).map_err(malformed),
+        }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Envelope, RateLimiterError> {
+        let envelope = match self {
+            Codec::Bincode if !bytes.starts_with(&MAGIC) => Err(malformed("not an envelope")),
+            Codec::Bincode => match bytes.get(MAGIC.len()..MAGIC.len() + 2) {
+                Some([1, 0]) => bincode::deserialize::<EnvelopeV1>(&bytes[MAGIC.len()..])
+                    .map(Envelope::from)
+                    .map_err(RateLimiterError::MalformedValue),
+                _ => bincode::deserialize(&bytes[MAGIC.len()..])
+                    .map_err(RateLimiterError::MalformedValue),
+            },
+            Codec::Compact => compact::decode(bytes),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::from_slice(bytes).map_err(malformed),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => rmp_serde::from_slice(bytes).map_err(malformed),
+        }?;
+        // a layout this release doesn't know, read as the current one it would be garbage
+        if envelope.version == 0 || envelope.version > FORMAT_VERSION {
+            return Err(malformed(format!(
+                "unknown format version {}",
+                envelope.version
+            )));
+        }
+        Ok(envelope)
+    }
+
+    // the part of a fingerprint the codec stores
+    fn fingerprint(&self, fingerprint: u64) -> u64 {
+        match self {
+            Codec::Compact => fingerprint & u32::MAX as u64,
+            _ => fingerprint,
+        }
+    }
+}
+
+// errors of other codecs are reported as `bincode` ones, `RateLimiterError::MalformedValue` predates them
+pub(crate) fn malformed(e: impl fmt::Display) -> RateLimiterError {
+    RateLimiterError::MalformedValue(Box::new(bincode::ErrorKind::Custom(e.to_string())))
+}
+
+/// What to do with an instance written by the same limiter under a different configuration, as told
+/// by `LimiterType::fingerprint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigChangePolicy {
+    /// Use the instance as is.
+    #[default]
+    Keep,
+    /// Scale the recorded usage by the ratio of the new `LimiterType::limit` to the previous one, ex:
+    /// 500 requests counted against a threshold of 1000 become 50 against a threshold of 100. Kept
+    /// as is if either limit is unknown.
+    Rescale,
+    /// Discard the instance, the key starts over.
+    Reset,
+}
+
+type Migration = Arc<dyn Fn(LimiterInstance) -> Option<LimiterInstance> + Send + Sync>;
+
+/// A stored instance along with what wrote it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope {
+    version: u16,
+    limiter: String,
+    #[serde(with = "wide")]
+    fingerprint: u64,
+    // missing from version 1 envelopes
+    #[serde(default)]
+    limit: Option<u32>,
+    instance: LimiterInstance,
+}
+
+// the version 1 layout, before `limit`
+#[derive(Deserialize)]
+struct EnvelopeV1 {
+    version: u16,
+    limiter: String,
+    #[serde(with = "wide")]
+    fingerprint: u64,
+    instance: LimiterInstance,
+}
+
+impl From<EnvelopeV1> for Envelope {
+    fn from(envelope: EnvelopeV1) -> Self {
+        Envelope {
+            version: envelope.version,
+            limiter: envelope.limiter,
+            fingerprint: envelope.fingerprint,
+            limit: None,
+            instance: envelope.instance,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// `LimiterType::name` of the limiter that wrote the instance.
+    pub fn limiter(&self) -> &str {
+        &self.limiter
+    }
+
+    /// `LimiterType::fingerprint` of the limiter that wrote the instance.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// `LimiterType::limit` of the limiter that wrote the instance.
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+
+    pub fn instance(&self) -> &LimiterInstance {
+        &self.instance
+    }
+
+    pub fn into_instance(self) -> LimiterInstance {
+        self.instance
+    }
+}
+
+// same layout as `Envelope`, without taking ownership of the instance
+#[derive(Serialize)]
+struct EnvelopeRef<'a> {
+    version: u16,
+    limiter: &'a str,
+    #[serde(with = "wide")]
+    fingerprint: u64,
+    limit: Option<u32>,
+    instance: &'a LimiterInstance,
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct Storage {
+    migrations: Vec<(String, Migration)>,
+    on_config_change: ConfigChangePolicy,
+    codec: Codec,
+}
+
+impl Storage {
+    pub(crate) fn set_codec(&mut self, codec: Codec) {
+        self.codec = codec;
+    }
+
+    pub(crate) fn set_config_change_policy(&mut self, policy: ConfigChangePolicy) {
+        self.on_config_change = policy;
+    }
+
+    pub(crate) fn add_migration<F>(&mut self, from: &str, migration: F)
+    where
+        F: Fn(LimiterInstance) -> Option<LimiterInstance> + Send + Sync + 'static,
+    {
+        self.migrations
+            .push((from.to_string(), Arc::new(migration)));
+    }
+
+    pub(crate) fn encode<T: LimiterType>(
+        &self,
+        limiter: &T,
+        instance: &LimiterInstance,
+    ) -> Result<Vec<u8>, RateLimiterError> {
+        self.codec.encode(&EnvelopeRef {
+            version: FORMAT_VERSION,
+            limiter: limiter.name(),
+            fingerprint: limiter.fingerprint(),
+            limit: limiter.limit(),
+            instance,
+        })
+    }
+
+    /// Decodes a stored value, migrating it if it was written by another limiter. `None` means the
+    /// key starts over.
+    pub(crate) fn decode<T: LimiterType>(
+        &self,
+        limiter: &T,
+        value: Vec<u8>,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        let envelope = match self.codec.decode(&value) {
+            Ok(envelope) => envelope,
+            // written before envelopes, nothing is known about the limiter's configuration
+            Err(e) => match legacy_instance(value) {
+                Ok(instance) => Envelope {
+                    version: 0,
+                    limiter: legacy_name(&instance).to_string(),
+                    fingerprint: self.codec.fingerprint(limiter.fingerprint()),
+                    limit: None,
+                    instance,
+                },
+                Err(_) => return Err(e),
+            },
+        };
+        let (written_by, mut instance) = (envelope.limiter, envelope.instance);
+
+        if written_by == limiter.name() {
+            if envelope.fingerprint == self.codec.fingerprint(limiter.fingerprint()) {
+                return Ok(Some(instance));
+            }
+            return match (self.on_config_change, envelope.limit, limiter.limit()) {
+                (ConfigChangePolicy::Reset, _, _) => Ok(None),
+                (ConfigChangePolicy::Rescale, Some(previous), Some(limit)) if previous > 0 => {
+                    instance.rescale(limit, previous);
+                    Ok(Some(instance))
+                }
+                _ => Ok(Some(instance)),
+            };
+        }
+        match self.migrations.iter().find(|(from, _)| *from == written_by) {
+            Some((_, migration)) => Ok(migration(instance)),
+            // left to the limiter, which rejects instances it can't read
+            None => Ok(Some(instance)),
+        }
+    }
+}
+
+impl fmt::Debug for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Storage")
+            .field(
+                "migrations",
+                &self
+                    .migrations
+                    .iter()
+                    .map(|(from, _)| from)
+                    .collect::<Vec<_>>(),
+            )
+            .field("on_config_change", &self.on_config_change)
+            .field("codec", &self.codec)
+            .finish()
+    }
+}
+
+// the layout of token buckets written before envelopes, their tokens counted as an f32
+#[derive(Deserialize)]
+struct LegacyTokenBucketInstance {
+    tokens: f32,
+    last_access: u128,
+}
+
+// bare instances, written before envelopes; token buckets are converted to fixed-point tokens
+fn legacy_instance(value: Vec<u8>) -> Result<LimiterInstance, RateLimiterError> {
+    // `TokenBucketInstance`'s variant index
+    if let Some(fields) = value.strip_prefix(&[2, 0, 0, 0]) {
+        let legacy: LegacyTokenBucketInstance =
+            bincode::deserialize(fields).map_err(RateLimiterError::MalformedValue)?;
+        return Ok(LimiterInstance::TokenBucketInstance(TokenBucketInstance {
+            tokens: (legacy.tokens.max(0.0) as f64 * TOKEN_SCALE as f64).round() as u64,
+            last_access: legacy.last_access,
+        }));
+    }
+    LimiterInstance::from_bytes(value)
+}
+
+// the limiter that wrote a bare instance, as far as it can be told from the instance alone
+fn legacy_name(instance: &LimiterInstance) -> &str {
+    match instance {
+        LimiterInstance::FixedWindowInstance(_) => "fixed_window",
+        LimiterInstance::SlidingWindowInstance(_) => "sliding_window_counter",
+        LimiterInstance::TokenBucketInstance(_) => "token_bucket",
+        LimiterInstance::LeakyBucketInstance(_) => "leaky_bucket",
+        LimiterInstance::SlidingWindowLogInstance(_) => "sliding_window_log",
+        LimiterInstance::ConcurrencyInstance(_) => "concurrency",
+        LimiterInstance::AdaptiveInstance(_) => "adaptive",
+        LimiterInstance::Custom { type_id, .. } => type_id,
+    }
+}
+
+/// Migrates `FixedWindow` instances to a `TokenBucket` of `capacity` tokens, with the requests
+/// already counted in the current window taken out of the bucket.
+pub fn fixed_window_to_token_bucket(
+    capacity: u32,
+) -> impl Fn(LimiterInstance) -> Option<LimiterInstance> + Send + Sync {
+    move |instance| {
+        let count = match instance {
+            LimiterInstance::FixedWindowInstance(i) => i.window_count(),
+            _ => return None,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        Some(LimiterInstance::TokenBucketInstance(
+            TokenBucketInstance::new(now, capacity - cmp::min(count, capacity)),
+        ))
+    }
+}
+
+/// Migrates `TokenBucket` instances to a `FixedWindow` of `threshold` requests, with the tokens
+/// already spent counted in a window starting now.
+pub fn token_bucket_to_fixed_window(
+    threshold: u32,
+) -> impl Fn(LimiterInstance) -> Option<LimiterInstance> + Send + Sync {
+    move |instance| {
+        let tokens = match instance {
+            LimiterInstance::TokenBucketInstance(i) => i.tokens(),
+            _ => return None,
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        Some(LimiterInstance::FixedWindowInstance(
+            FixedWindowInstance::new(now, threshold - cmp::min(tokens, threshold)),
+        ))
+    }
+}
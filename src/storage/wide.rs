@@ -0,0 +1,122 @@
+//! Serde helpers for 64 and 128-bit integers, written as strings by human-readable codecs (`Json`)
+//! since readers parsing numbers as doubles lose precision past 2^53. Numbers are still read.
+
+use serde::{
+    de::{self, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+struct Wide<T>(T);
+
+impl<T: Serialize + fmt::Display> Serialize for Wide<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&self.0)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Wide<T>
+where
+    T: Deserialize<'de> + FromStr + TryFrom<u64>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if !deserializer.is_human_readable() {
+            return T::deserialize(deserializer).map(Wide);
+        }
+        deserializer.deserialize_any(WideVisitor(PhantomData))
+    }
+}
+
+struct WideVisitor<T>(PhantomData<T>);
+
+impl<T: FromStr + TryFrom<u64>> Visitor<'_> for WideVisitor<T> {
+    type Value = Wide<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an unsigned integer or a string holding one")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        v.parse()
+            .map(Wide)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+        T::try_from(v)
+            .map(Wide)
+            .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+}
+
+pub(crate) fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize + fmt::Display,
+    S: Serializer,
+{
+    Wide(value).serialize(serializer)
+}
+
+pub(crate) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Deserialize<'de> + FromStr + TryFrom<u64>,
+    D: Deserializer<'de>,
+{
+    Wide::deserialize(deserializer).map(|w| w.0)
+}
+
+/// Lists of `(wide, narrow)` pairs, ex: `SlidingWindowLogInstance::entries`.
+pub(crate) mod first {
+    use super::Wide;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::{fmt, str::FromStr};
+
+    pub(crate) fn serialize<A, B, S>(pairs: &[(A, B)], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        A: Serialize + fmt::Display,
+        B: Serialize,
+        S: Serializer,
+    {
+        serializer.collect_seq(pairs.iter().map(|(a, b)| (Wide(a), b)))
+    }
+
+    pub(crate) fn deserialize<'de, A, B, D>(deserializer: D) -> Result<Vec<(A, B)>, D::Error>
+    where
+        A: Deserialize<'de> + FromStr + TryFrom<u64>,
+        B: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        let pairs = Vec::<(Wide<A>, B)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().map(|(a, b)| (a.0, b)).collect())
+    }
+}
+
+/// Lists of `(wide, wide)` pairs, ex: `ConcurrencyInstance::leases`.
+pub(crate) mod both {
+    use super::Wide;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::{fmt, str::FromStr};
+
+    pub(crate) fn serialize<A, B, S>(pairs: &[(A, B)], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        A: Serialize + fmt::Display,
+        B: Serialize + fmt::Display,
+        S: Serializer,
+    {
+        serializer.collect_seq(pairs.iter().map(|(a, b)| (Wide(a), Wide(b))))
+    }
+
+    pub(crate) fn deserialize<'de, A, B, D>(deserializer: D) -> Result<Vec<(A, B)>, D::Error>
+    where
+        A: Deserialize<'de> + FromStr + TryFrom<u64>,
+        B: Deserialize<'de> + FromStr + TryFrom<u64>,
+        D: Deserializer<'de>,
+    {
+        let pairs = Vec::<(Wide<A>, Wide<B>)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().map(|(a, b)| (a.0, b.0)).collect())
+    }
+}
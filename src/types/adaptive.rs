@@ -0,0 +1,306 @@
+use super::{
+    check_duration, fingerprint, fixed_window::FixedWindowInstance, rescaled, InvalidParameter,
+    LimiterInstance, LimiterType, Outcome, RateLimiterError,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A fixed window limiter whose threshold adapts to feedback reported with `RateLimiter::report`.
+///
+/// The limit grows by `increase` once a limit's worth of successes were reported (additive
+/// increase), and is multiplied by `decrease_factor` on every reported failure (multiplicative
+/// decrease). It always stays within `min_limit..=max_limit`.
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    min_limit: u32,
+    max_limit: u32,
+    window_length: Duration,
+    initial_limit: u32,
+    increase: u32,
+    decrease_factor: f64,
+    latency_threshold: Option<Duration>,
+}
+
+impl Adaptive {
+    pub fn new(min_limit: u32, max_limit: u32, window_length: Duration) -> Self {
+        Adaptive {
+            min_limit,
+            max_limit,
+            window_length,
+            initial_limit: max_limit,
+            increase: 1,
+            decrease_factor: 0.5,
+            latency_threshold: None,
+        }
+    }
+
+    /// Limit new keys start with. Defaults to `max_limit`.
+    pub fn with_initial_limit(mut self, limit: u32) -> Self {
+        self.initial_limit = limit;
+        self
+    }
+
+    /// Additive step, defaults to 1.
+    pub fn with_increase(mut self, increase: u32) -> Self {
+        self.increase = increase;
+        self
+    }
+
+    /// Multiplicative step, defaults to 0.5.
+    pub fn with_decrease_factor(mut self, factor: f64) -> Self {
+        self.decrease_factor = factor;
+        self
+    }
+
+    /// Treat `Outcome::Latency` reports above `threshold` as failures.
+    pub fn with_latency_threshold(mut self, threshold: Duration) -> Self {
+        self.latency_threshold = Some(threshold);
+        self
+    }
+
+    fn clamp(&self, limit: u32) -> u32 {
+        cmp::min(cmp::max(limit, self.min_limit), self.max_limit)
+    }
+}
+
+impl LimiterType for Adaptive {
+    fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.is_rate_limited_now(now, bytes)
+    }
+
+    fn report(
+        &self,
+        bytes: Vec<u8>,
+        outcome: &Outcome,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        let mut instance = self.window_instance(bytes)?.as_adaptive_instance()?;
+
+        let failed = match outcome {
+            Outcome::Success => false,
+            Outcome::Failure => true,
+            Outcome::Latency(latency) => self.latency_threshold.is_some_and(|t| *latency > t),
+        };
+        if failed {
+            instance.limit = self.clamp((instance.limit as f64 * self.decrease_factor) as u32);
+            instance.successes = 0;
+        } else {
+            instance.successes += 1;
+            if instance.successes >= instance.limit {
+                instance.limit = self.clamp(instance.limit.saturating_add(self.increase));
+                instance.successes = 0;
+            }
+        }
+        Ok(Some(LimiterInstance::AdaptiveInstance(instance)))
+    }
+
+    fn wants_feedback(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "adaptive"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.max_limit)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::AdaptiveInstance(i) => {
+                Some(i.limit().saturating_sub(i.window().window_count()))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[
+            self.min_limit as u128,
+            self.max_limit as u128,
+            self.window_length.as_millis(),
+            self.initial_limit as u128,
+            self.increase as u128,
+            self.decrease_factor.to_bits() as u128,
+            self.latency_threshold.map_or(0, |t| t.as_millis() + 1),
+        ])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("window_length", self.window_length)?;
+        // a limit of zero would deny every request, and no success could be reported to raise it
+        if self.min_limit == 0 {
+            return Err(InvalidParameter::new(
+                "min_limit",
+                "must be greater than zero",
+            ));
+        }
+        if self.min_limit > self.max_limit {
+            return Err(InvalidParameter::new(
+                "min_limit",
+                "must not exceed max_limit",
+            ));
+        }
+        // also rejects NaN
+        if !(self.decrease_factor > 0.0 && self.decrease_factor < 1.0) {
+            return Err(InvalidParameter::new(
+                "decrease_factor",
+                "must be between 0 and 1",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Adaptive {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let mut instance = match bytes {
+            Some(b) => {
+                let mut instance = self.window_instance(b)?.as_adaptive_instance()?;
+                // the range may have changed since the limit was stored
+                instance.limit = self.clamp(instance.limit);
+                instance
+            }
+            None => AdaptiveInstance {
+                limit: self.clamp(self.initial_limit),
+                successes: 0,
+                window: FixedWindowInstance::new(now, 0),
+            },
+        };
+
+        if now.saturating_sub(instance.window.window_start()) >= self.window_length.as_millis() {
+            instance.window = FixedWindowInstance::new(now, 0);
+        }
+        if instance.window.window_count() >= instance.limit {
+            return Err(RateLimiterError::RateExceeded);
+        }
+        instance.window.count += 1;
+        Ok(LimiterInstance::AdaptiveInstance(instance))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AdaptiveInstance {
+    pub(crate) limit: u32,
+    pub(crate) successes: u32,
+    pub(crate) window: FixedWindowInstance,
+}
+
+impl AdaptiveInstance {
+    /// The effective limit for the current window.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    /// Successes reported since the limit last changed.
+    pub fn successes(&self) -> u32 {
+        self.successes
+    }
+
+    pub fn window(&self) -> &FixedWindowInstance {
+        &self.window
+    }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        self.limit = rescaled(self.limit, limit, previous_limit);
+        self.successes = rescaled(self.successes, limit, previous_limit);
+        self.window.rescale(limit, previous_limit);
+    }
+}
+
+#[test]
+fn adaptive() {
+    use crate::types::SerializableInstance;
+
+    let adaptive = Adaptive::new(2, 10, Duration::from_millis(100))
+        .with_initial_limit(4)
+        .with_latency_threshold(Duration::from_millis(500));
+    let limit = |bytes: &Vec<u8>| {
+        adaptive
+            .window_instance(bytes.clone())
+            .unwrap()
+            .as_adaptive_instance()
+            .unwrap()
+            .limit()
+    };
+
+    let mut bytes = adaptive
+        .is_rate_limited_now(0, None)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+    assert_eq!(limit(&bytes), 4);
+
+    // a limit's worth of successes increases the limit by one
+    for _ in 0..4 {
+        bytes = adaptive
+            .report(bytes, &Outcome::Latency(Duration::from_millis(10)))
+            .unwrap()
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+    }
+    assert_eq!(limit(&bytes), 5);
+
+    // failures halve it, down to min_limit
+    for expected in [2, 2] {
+        bytes = adaptive
+            .report(bytes, &Outcome::Latency(Duration::from_secs(1)))
+            .unwrap()
+            .unwrap()
+            .to_bytes()
+            .unwrap();
+        assert_eq!(limit(&bytes), expected);
+    }
+
+    // the current window only admits up to the adapted limit
+    for i in 0..3 {
+        let result = adaptive.is_rate_limited_now(50, Some(bytes.clone()));
+        assert!(result.is_ok() == (i < 1));
+        if let Ok(i) = result {
+            bytes = i.to_bytes().unwrap();
+        }
+    }
+}
+
+#[test]
+fn stored_limit_clamped() {
+    use crate::types::SerializableInstance;
+
+    let stored = Adaptive::new(1, 10, Duration::from_millis(100))
+        .is_rate_limited_now(0, None)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+
+    // the range shrank since the limit of 10 was stored, with one request counted
+    let narrower = Adaptive::new(1, 3, Duration::from_millis(100));
+    let mut bytes = stored;
+    for i in 0..4 {
+        let result = narrower.is_rate_limited_now(50, Some(bytes.clone()));
+        assert!(result.is_ok() == (i < 2));
+        if let Ok(i) = result {
+            bytes = i.to_bytes().unwrap();
+            assert_eq!(
+                narrower
+                    .window_instance(bytes.clone())
+                    .unwrap()
+                    .as_adaptive_instance()
+                    .unwrap()
+                    .limit(),
+                3
+            );
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use super::{
+    adaptive::Adaptive, calendar::CalendarWindow, concurrency::ConcurrencyLimiter,
+    fixed_window::FixedWindow, leaky_bucket::LeakyBucket, sliding_window::SlidingWindowCounter,
+    sliding_window_log::SlidingWindowLog, token_bucket::TokenBucket, InvalidParameter,
+    LimiterInstance, LimiterType, Outcome, RateLimiterError,
+};
+use std::time::Duration;
+
+/// Any of the built-in limiters, for when the algorithm is only known at runtime (see `config`).
+#[derive(Debug, Clone)]
+pub enum AnyLimiter {
+    FixedWindow(FixedWindow),
+    CalendarWindow(CalendarWindow),
+    SlidingWindowCounter(SlidingWindowCounter),
+    SlidingWindowLog(SlidingWindowLog),
+    TokenBucket(TokenBucket),
+    LeakyBucket(LeakyBucket),
+    Concurrency(ConcurrencyLimiter),
+    Adaptive(Adaptive),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $limiter:ident => $call:expr) => {
+        match $self {
+            AnyLimiter::FixedWindow($limiter) => $call,
+            AnyLimiter::CalendarWindow($limiter) => $call,
+            AnyLimiter::SlidingWindowCounter($limiter) => $call,
+            AnyLimiter::SlidingWindowLog($limiter) => $call,
+            AnyLimiter::TokenBucket($limiter) => $call,
+            AnyLimiter::LeakyBucket($limiter) => $call,
+            AnyLimiter::Concurrency($limiter) => $call,
+            AnyLimiter::Adaptive($limiter) => $call,
+        }
+    };
+}
+
+impl LimiterType for AnyLimiter {
+    fn is_ratelimited(&self, value: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        dispatch!(self, l => l.is_ratelimited(value))
+    }
+
+    fn window_instance(&self, value: Vec<u8>) -> Result<LimiterInstance, RateLimiterError> {
+        dispatch!(self, l => l.window_instance(value))
+    }
+
+    fn acquire(
+        &self,
+        value: Option<Vec<u8>>,
+        lease: u64,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        dispatch!(self, l => l.acquire(value, lease))
+    }
+
+    fn release(
+        &self,
+        value: Vec<u8>,
+        lease: u64,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        dispatch!(self, l => l.release(value, lease))
+    }
+
+    fn delay(&self, instance: &LimiterInstance) -> Duration {
+        dispatch!(self, l => l.delay(instance))
+    }
+
+    fn report(
+        &self,
+        value: Vec<u8>,
+        outcome: &Outcome,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        dispatch!(self, l => l.report(value, outcome))
+    }
+
+    fn wants_feedback(&self) -> bool {
+        dispatch!(self, l => l.wants_feedback())
+    }
+
+    fn holds_leases(&self) -> bool {
+        dispatch!(self, l => l.holds_leases())
+    }
+
+    fn name(&self) -> &str {
+        dispatch!(self, l => l.name())
+    }
+
+    fn limit(&self) -> Option<u32> {
+        dispatch!(self, l => l.limit())
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        dispatch!(self, l => l.remaining(instance))
+    }
+
+    fn fingerprint(&self) -> u64 {
+        dispatch!(self, l => l.fingerprint())
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        dispatch!(self, l => l.validate())
+    }
+}
+
+macro_rules! from {
+    ($($variant:ident($limiter:ty)),*) => {
+        $(
+            impl From<$limiter> for AnyLimiter {
+                fn from(limiter: $limiter) -> Self {
+                    AnyLimiter::$variant(limiter)
+                }
+            }
+        )*
+    };
+}
+
+from!(
+    FixedWindow(FixedWindow),
+    CalendarWindow(CalendarWindow),
+    SlidingWindowCounter(SlidingWindowCounter),
+    SlidingWindowLog(SlidingWindowLog),
+    TokenBucket(TokenBucket),
+    LeakyBucket(LeakyBucket),
+    Concurrency(ConcurrencyLimiter),
+    Adaptive(Adaptive)
+);
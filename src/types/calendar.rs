@@ -0,0 +1,308 @@
+use super::{
+    check_duration, fingerprint, fixed_window::FixedWindowInstance, InvalidParameter,
+    LimiterInstance, LimiterType, RateLimiterError,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: i64 = 24 * 60 * 60 * 1000;
+
+/// Length of a `CalendarWindow`.
+#[derive(Debug, Clone, Copy)]
+pub enum Period {
+    /// Windows of a fixed length, aligned to multiples of it since the unix epoch.
+    Every(Duration),
+    /// Calendar days, starting at midnight.
+    Day,
+    /// Calendar weeks, starting on Monday at midnight.
+    Week,
+    /// Calendar months, starting on the first at midnight.
+    Month,
+}
+
+#[derive(Debug, Clone)]
+enum Zone {
+    Offset(i64), // ms east of UTC
+    #[cfg(feature = "timezone")]
+    Tz(chrono_tz::Tz),
+}
+
+/// A fixed window quota whose windows are shared by all keys and follow the calendar, ex: 1000 calls
+/// per day, resetting at midnight.
+///
+/// Boundaries are computed in UTC unless a fixed offset (`with_utc_offset`) or an IANA timezone
+/// (`with_timezone`, requires the `timezone` feature) is configured.
+#[derive(Debug, Clone)]
+pub struct CalendarWindow {
+    threshold: u32,
+    period: Period,
+    zone: Zone,
+}
+
+impl CalendarWindow {
+    pub fn new(threshold: u32, period: Period) -> Self {
+        CalendarWindow {
+            threshold,
+            period,
+            zone: Zone::Offset(0),
+        }
+    }
+
+    /// Compute boundaries at a fixed offset from UTC, in seconds (east is positive).
+    pub fn with_utc_offset(mut self, offset_secs: i32) -> Self {
+        self.zone = Zone::Offset(offset_secs as i64 * 1000);
+        self
+    }
+
+    /// Compute boundaries in an IANA timezone, following its daylight saving transitions.
+    #[cfg(feature = "timezone")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "timezone")))]
+    pub fn with_timezone(mut self, tz: chrono_tz::Tz) -> Self {
+        self.zone = Zone::Tz(tz);
+        self
+    }
+
+    /// Start and end (exclusive) of the window containing `now`, in milliseconds since the unix epoch.
+    pub fn window_bounds(&self, now: u128) -> (u128, u128) {
+        let now = now as i64;
+        let offset = self.offset_at(now);
+        let local = now + offset;
+
+        let (start_day, end_day) = match self.period {
+            Period::Every(length) => {
+                let length = length.as_millis() as i64;
+                let start = local.div_euclid(length) * length - offset;
+                return (start as u128, (start + length) as u128);
+            }
+            Period::Day => {
+                let day = local.div_euclid(DAY);
+                (day, day + 1)
+            }
+            Period::Week => {
+                let day = local.div_euclid(DAY);
+                // the epoch was a thursday
+                let monday = day - (day + 3).rem_euclid(7);
+                (monday, monday + 7)
+            }
+            Period::Month => {
+                let (year, month, _) = civil_from_days(local.div_euclid(DAY));
+                let (next_year, next_month) = match month {
+                    12 => (year + 1, 1),
+                    m => (year, m + 1),
+                };
+                (
+                    days_from_civil(year, month, 1),
+                    days_from_civil(next_year, next_month, 1),
+                )
+            }
+        };
+        (
+            self.midnight(start_day) as u128,
+            self.midnight(end_day) as u128,
+        )
+    }
+
+    #[cfg_attr(not(feature = "timezone"), allow(unused_variables))]
+    fn offset_at(&self, now: i64) -> i64 {
+        match &self.zone {
+            Zone::Offset(offset) => *offset,
+            #[cfg(feature = "timezone")]
+            Zone::Tz(tz) => {
+                use chrono::{Offset, TimeZone};
+
+                let utc = chrono::DateTime::from_timestamp_millis(now).unwrap_or_default();
+                tz.offset_from_utc_datetime(&utc.naive_utc())
+                    .fix()
+                    .local_minus_utc() as i64
+                    * 1000
+            }
+        }
+    }
+
+    // utc timestamp of midnight, local time, `day` days after the epoch
+    fn midnight(&self, day: i64) -> i64 {
+        match &self.zone {
+            Zone::Offset(offset) => day * DAY - offset,
+            #[cfg(feature = "timezone")]
+            Zone::Tz(tz) => {
+                use chrono::TimeZone;
+
+                let date = chrono::DateTime::from_timestamp_millis(day * DAY)
+                    .unwrap_or_default()
+                    .date_naive();
+                // midnight can fall into a daylight saving gap, the window then starts when the day does
+                (0..24)
+                    .find_map(|hour| {
+                        let local = date.and_hms_opt(hour, 0, 0)?;
+                        tz.from_local_datetime(&local).earliest()
+                    })
+                    .map_or(day * DAY, |t| t.timestamp_millis())
+            }
+        }
+    }
+}
+
+impl LimiterType for CalendarWindow {
+    fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.is_rate_limited_now(now, bytes)
+    }
+
+    fn name(&self) -> &str {
+        "calendar_window"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.threshold)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::FixedWindowInstance(i) => {
+                Some(self.threshold.saturating_sub(i.window_count()))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        let (period, length) = match self.period {
+            Period::Every(length) => (0, length.as_millis()),
+            Period::Day => (1, 0),
+            Period::Week => (2, 0),
+            Period::Month => (3, 0),
+        };
+        let zone = match &self.zone {
+            Zone::Offset(offset) => *offset as u128,
+            #[cfg(feature = "timezone")]
+            Zone::Tz(tz) => {
+                fingerprint(&tz.name().bytes().map(u128::from).collect::<Vec<_>>()) as u128
+            }
+        };
+        fingerprint(&[self.threshold as u128, period, length, zone])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        match self.period {
+            Period::Every(length) => check_duration("period", length),
+            Period::Day | Period::Week | Period::Month => Ok(()),
+        }
+    }
+}
+
+impl CalendarWindow {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let (window_start, _) = self.window_bounds(now);
+        let mut instance = match bytes {
+            Some(b) => self.window_instance(b)?.as_fixed_window_instance()?,
+            None => FixedWindowInstance::new(window_start, 0),
+        };
+
+        if instance.window_start() != window_start {
+            instance = FixedWindowInstance::new(window_start, 0);
+        }
+        if instance.count >= self.threshold {
+            return Err(RateLimiterError::RateExceeded);
+        }
+        instance.count += 1;
+        Ok(LimiterInstance::FixedWindowInstance(instance))
+    }
+}
+
+// days since the epoch to (year, month, day), see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[test]
+fn calendar_window() {
+    use crate::types::SerializableInstance;
+
+    const H: u128 = 60 * 60 * 1000;
+    let jan_1 = 1_704_067_200_000u128; // 2024-01-01T00:00:00Z, a monday
+    let feb_1 = 1_706_745_600_000u128;
+
+    let day = CalendarWindow::new(2, Period::Day);
+    assert_eq!(day.window_bounds(jan_1 + 13 * H), (jan_1, jan_1 + 24 * H));
+
+    // wednesday belongs to the week starting on monday
+    let week = CalendarWindow::new(2, Period::Week);
+    assert_eq!(
+        week.window_bounds(jan_1 + 50 * H),
+        (jan_1, jan_1 + 7 * 24 * H)
+    );
+
+    // 23:30 UTC on jan 31 is already february at UTC+1
+    let month = CalendarWindow::new(2, Period::Month).with_utc_offset(3600);
+    assert_eq!(
+        month.window_bounds(feb_1 - H / 2),
+        (feb_1 - H, 1_709_251_200_000 - H)
+    );
+    let month = CalendarWindow::new(2, Period::Month);
+    assert_eq!(month.window_bounds(feb_1 - H / 2), (jan_1, feb_1));
+
+    // fixed length windows are aligned to the epoch, shifted by the offset
+    let every =
+        CalendarWindow::new(2, Period::Every(Duration::from_secs(3600))).with_utc_offset(-30 * 60);
+    assert_eq!(
+        every.window_bounds(jan_1 + 5 * H),
+        (jan_1 + 5 * H - H / 2, jan_1 + 5 * H + H / 2)
+    );
+
+    // windows are shared by all keys and reset at the boundary
+    let mut instance = None;
+    for (ts, allowed) in [
+        (jan_1 + H, true),
+        (jan_1 + 2 * H, true),
+        (jan_1 + 3 * H, false),
+    ] {
+        let result = day.is_rate_limited_now(ts, instance.clone());
+        assert_eq!(result.is_ok(), allowed);
+        if let Ok(i) = result {
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+    let result = day.is_rate_limited_now(jan_1 + 24 * H, instance).unwrap();
+    let instance = result.as_fixed_window_instance().unwrap();
+    assert_eq!(instance.window_start(), jan_1 + 24 * H);
+    assert_eq!(instance.window_count(), 1);
+}
+
+#[cfg(feature = "timezone")]
+#[test]
+fn calendar_window_timezone() {
+    const H: u128 = 60 * 60 * 1000;
+    let mar_10 = 1_710_028_800_000u128; // 2024-03-10T00:00:00Z, daylight saving starts in New York
+
+    let day = CalendarWindow::new(2, Period::Day).with_timezone(chrono_tz::America::New_York);
+    // midnight EST to midnight EDT, a 23 hour day
+    assert_eq!(
+        day.window_bounds(mar_10 + 12 * H),
+        (mar_10 + 5 * H, mar_10 + 28 * H)
+    );
+}
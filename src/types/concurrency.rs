@@ -0,0 +1,129 @@
+use super::{
+    check_duration, fingerprint, InvalidParameter, LimiterInstance, LimiterType, RateLimiterError,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Caps the number of in-flight leases per key.
+///
+/// Leases are taken with `RateLimiter::acquire` and given back when the returned `Lease` is dropped.
+/// A lease that is never released (for example because its holder crashed) expires after `lease_ttl`.
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimiter {
+    max_concurrent: u32,
+    lease_ttl: Duration,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_concurrent: u32, lease_ttl: Duration) -> Self {
+        ConcurrencyLimiter {
+            max_concurrent,
+            lease_ttl,
+        }
+    }
+}
+
+impl LimiterType for ConcurrencyLimiter {
+    /// Takes an anonymous lease that is only ever freed by expiring.
+    fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        self.acquire(bytes, 0)
+    }
+
+    fn acquire(
+        &self,
+        bytes: Option<Vec<u8>>,
+        lease: u64,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.acquire_now(now, bytes, lease)
+    }
+
+    fn release(
+        &self,
+        bytes: Vec<u8>,
+        lease: u64,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        let mut instance = self.window_instance(bytes)?.as_concurrency_instance()?;
+        match instance.leases.iter().position(|(id, _)| *id == lease) {
+            Some(i) => {
+                instance.leases.remove(i);
+                Ok(Some(LimiterInstance::ConcurrencyInstance(instance)))
+            }
+            // already expired and dropped by another acquire
+            None => Ok(None),
+        }
+    }
+
+    fn holds_leases(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &str {
+        "concurrency"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.max_concurrent)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::ConcurrencyInstance(i) => {
+                Some(self.max_concurrent.saturating_sub(i.in_flight()))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[self.max_concurrent as u128, self.lease_ttl.as_millis()])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("lease_ttl", self.lease_ttl)
+    }
+}
+
+impl ConcurrencyLimiter {
+    fn acquire_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+        lease: u64,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let mut instance = match bytes {
+            Some(b) => self.window_instance(b)?.as_concurrency_instance()?,
+            None => ConcurrencyInstance { leases: vec![] },
+        };
+
+        instance.leases.retain(|(_, expires_at)| *expires_at > now);
+
+        if instance.leases.len() >= self.max_concurrent as usize {
+            return Err(RateLimiterError::RateExceeded);
+        }
+        instance
+            .leases
+            .push((lease, now + self.lease_ttl.as_millis()));
+        Ok(LimiterInstance::ConcurrencyInstance(instance))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConcurrencyInstance {
+    #[serde(with = "crate::storage::wide::both")]
+    pub(crate) leases: Vec<(u64, u128)>,
+}
+
+impl ConcurrencyInstance {
+    /// Held leases as `(lease id, expires_at)` pairs. Expired leases are only dropped on the next acquire.
+    pub fn leases(&self) -> &[(u64, u128)] {
+        &self.leases
+    }
+
+    pub fn in_flight(&self) -> u32 {
+        self.leases.len() as u32
+    }
+}
@@ -1,11 +1,36 @@
-use super::{LimiterInstance, LimiterType, RateLimiterError};
+use super::{
+    check_duration, fingerprint, rescaled, InvalidParameter, LimiterInstance, LimiterType,
+    RateLimiterError,
+};
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+/// Where windows start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowAlignment {
+    /// A key's window starts with its first request after the previous window ended.
+    #[default]
+    FirstRequest,
+    /// Windows start at multiples of the window length since the unix epoch, and are shared by all keys.
+    Epoch,
+}
+
+impl WindowAlignment {
+    // start of the window containing `now`, `None` if it depends on the key
+    pub(crate) fn window_start(&self, now: u128, window_length: u128) -> Option<u128> {
+        match self {
+            WindowAlignment::FirstRequest => None,
+            WindowAlignment::Epoch => Some(now - now % window_length),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FixedWindow {
     threshold: u32,
     window_length: Duration,
+    alignment: WindowAlignment,
 }
 
 impl FixedWindow {
@@ -13,8 +38,14 @@ impl FixedWindow {
         FixedWindow {
             threshold,
             window_length,
+            alignment: WindowAlignment::FirstRequest,
         }
     }
+
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
 }
 
 impl LimiterType for FixedWindow {
@@ -23,16 +54,61 @@ impl LimiterType for FixedWindow {
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
+        self.is_rate_limited_now(now, bytes)
+    }
+
+    fn name(&self) -> &str {
+        "fixed_window"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.threshold)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::FixedWindowInstance(i) => {
+                Some(self.threshold.saturating_sub(i.window_count()))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[
+            self.threshold as u128,
+            self.window_length.as_millis(),
+            self.alignment as u128,
+        ])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("window_length", self.window_length)
+    }
+}
+
+impl FixedWindow {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let window_length = self.window_length.as_millis();
+        let aligned_start = self.alignment.window_start(now, window_length);
         let mut instance = match bytes {
             Some(b) => self.window_instance(b)?.as_fixed_window_instance()?,
             None => FixedWindowInstance {
-                window_start: now,
+                window_start: aligned_start.unwrap_or(now),
                 count: 0,
             },
         };
 
-        if now - instance.window_start >= self.window_length.as_millis() {
-            instance.window_start = now;
+        let expired = match aligned_start {
+            Some(start) => instance.window_start != start,
+            None => now - instance.window_start >= window_length,
+        };
+        if expired {
+            instance.window_start = aligned_start.unwrap_or(now);
             instance.count = 0;
         };
         if instance.count >= self.threshold {
@@ -45,7 +121,8 @@ impl LimiterType for FixedWindow {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FixedWindowInstance {
-    window_start: u128,
+    #[serde(with = "crate::storage::wide")]
+    pub(crate) window_start: u128,
     pub(crate) count: u32,
 }
 
@@ -63,4 +140,33 @@ impl FixedWindowInstance {
     pub fn window_count(&self) -> u32 {
         self.count
     }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        self.count = rescaled(self.count, limit, previous_limit);
+    }
+}
+
+#[test]
+fn fixed_window_aligned() {
+    use crate::types::SerializableInstance;
+
+    let window =
+        FixedWindow::new(2, Duration::from_millis(100)).with_alignment(WindowAlignment::Epoch);
+    let mut instance = None;
+
+    // the first request at 1050 still belongs to the window starting at 1000
+    for (ts, allowed) in [(1050, true), (1060, true), (1099, false), (1100, true)] {
+        let result = window.is_rate_limited_now(ts, instance.clone());
+        assert_eq!(result.is_ok(), allowed);
+        if let Ok(i) = result {
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+    let instance = window
+        .window_instance(instance.unwrap())
+        .unwrap()
+        .as_fixed_window_instance()
+        .unwrap();
+    assert_eq!(instance.window_start(), 1100);
+    assert_eq!(instance.window_count(), 1);
 }
@@ -1,14 +1,16 @@
-use super::{LimiterInstance, LimiterType, RateLimiterError};
-use serde::{Deserialize, Serialize};
-use std::{
-    cmp,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+use super::{
+    check_duration, fingerprint, rescaled, InvalidParameter, LimiterInstance, LimiterType,
+    RateLimiterError,
 };
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone)]
 pub struct LeakyBucket {
     capacity: u32,
-    leak_frequency: Duration, // leak 1 request per leak_frequency
+    leak_frequency: Duration, // leak leak_amount requests per leak_frequency
+    leak_amount: u32,
+    max_queue_wait: Option<Duration>,
 }
 
 impl LeakyBucket {
@@ -16,16 +18,87 @@ impl LeakyBucket {
         LeakyBucket {
             capacity,
             leak_frequency,
+            leak_amount: 1,
+            max_queue_wait: None,
         }
     }
+
+    /// Leak `amount` requests per `leak_frequency` instead of one, spread evenly over the period.
+    ///
+    /// Rates that aren't a whole number of milliseconds per request stay exact,
+    /// `LeakyBucket::new(3, Duration::from_secs(1)).with_leak_amount(3)` leaks 3 requests per second
+    /// where a `leak_frequency` of 333ms would leak slightly more.
+    pub fn with_leak_amount(mut self, amount: u32) -> Self {
+        self.leak_amount = amount;
+        self
+    }
+
+    /// Shape traffic instead of rejecting it: once the bucket is full, requests are queued and
+    /// admitted with the delay after which they may proceed (see `RateLimiter::reserve`).
+    ///
+    /// Requests that would have to wait longer than `max_wait` are still rejected.
+    pub fn with_max_queue_wait(mut self, max_wait: Duration) -> Self {
+        self.max_queue_wait = Some(max_wait);
+        self
+    }
 }
 
 impl LimiterType for LeakyBucket {
     fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
+        self.is_rate_limited_now(now(), bytes)
+    }
+
+    fn delay(&self, instance: &LimiterInstance) -> Duration {
+        match instance {
+            LimiterInstance::LeakyBucketInstance(i) => self.delay_now(now(), i),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn name(&self) -> &str {
+        "leaky_bucket"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.capacity)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        // queued requests (see `with_max_queue_wait`) take the bucket past its capacity
+        match instance {
+            LimiterInstance::LeakyBucketInstance(i) => {
+                Some(self.capacity.saturating_sub(self.level(now(), i)))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[
+            self.capacity as u128,
+            self.leak_frequency.as_millis(),
+            self.leak_amount as u128,
+            self.max_queue_wait.map_or(0, |w| w.as_millis() + 1),
+        ])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        if self.leak_amount == 0 {
+            return Err(InvalidParameter::new(
+                "leak_amount",
+                "must be greater than zero",
+            ));
+        }
+        check_duration("leak_frequency", self.leak_frequency)
+    }
+}
+
+impl LeakyBucket {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
         let mut instance = match bytes {
             Some(b) => self.window_instance(b)?.as_leaky_bucket_instance()?,
             None => LeakyBucketInstance {
@@ -34,26 +107,81 @@ impl LimiterType for LeakyBucket {
             },
         };
 
-        let elapsed = now - instance.last_leaked();
+        let leak_frequency = self.leak_frequency.as_millis();
+        let leak_amount = self.leak_amount as u128;
+        let elapsed = now.saturating_sub(instance.last_leaked());
 
-        instance.processed -= cmp::min(
-            (elapsed as f64 / self.leak_frequency.as_millis() as f64).floor() as u32,
-            instance.processed,
-        );
-        instance.last_leaked = now;
+        if self.leaks(elapsed) >= instance.processed as u128 {
+            // an empty bucket doesn't build up leaks for later requests
+            instance.processed = 0;
+            instance.last_leaked = now;
+        } else {
+            // carry over the progress towards the next leak, in whole cycles: the shortest span of
+            // whole milliseconds holding a whole number of leaks. Leaks within the current cycle
+            // are counted by `level` until it completes.
+            let cycle = leak_frequency / gcd(leak_frequency, leak_amount);
+            let cycles = elapsed / cycle;
+            instance.processed -= (cycles * cycle * leak_amount / leak_frequency) as u32;
+            instance.last_leaked += cycles * cycle;
+        }
 
-        if instance.processed >= self.capacity {
-            return Err(RateLimiterError::RateExceeded);
+        if self.level(now, &instance) >= self.capacity {
+            match self.max_queue_wait {
+                // queued behind everything already in the bucket
+                Some(max_wait) if self.wait_for(now, &instance, 1) <= max_wait.as_millis() => {}
+                _ => return Err(RateLimiterError::RateExceeded),
+            }
         }
         instance.processed += 1;
         Ok(LimiterInstance::LeakyBucketInstance(instance))
     }
+
+    fn delay_now(&self, now: u128, instance: &LeakyBucketInstance) -> Duration {
+        if self.max_queue_wait.is_none() || self.level(now, instance) <= self.capacity {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(self.wait_for(now, instance, 0) as u64)
+    }
+
+    // requests leaked `elapsed` ms after `last_leaked`
+    fn leaks(&self, elapsed: u128) -> u128 {
+        elapsed * self.leak_amount as u128 / self.leak_frequency.as_millis()
+    }
+
+    // requests in the bucket at `now`
+    fn level(&self, now: u128, instance: &LeakyBucketInstance) -> u32 {
+        let leaks = self.leaks(now.saturating_sub(instance.last_leaked));
+        instance.processed - leaks.min(instance.processed as u128) as u32
+    }
+
+    // time until `extra` more requests than currently in the bucket fit under capacity
+    fn wait_for(&self, now: u128, instance: &LeakyBucketInstance, extra: u32) -> u128 {
+        let leaks = (instance.processed + extra).saturating_sub(self.capacity) as u128;
+        let leak_amount = self.leak_amount as u128;
+        let leak_time = (leaks * self.leak_frequency.as_millis()).div_ceil(leak_amount);
+        (instance.last_leaked + leak_time).saturating_sub(now)
+    }
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LeakyBucketInstance {
-    processed: u32,
-    last_leaked: u128,
+    pub(crate) processed: u32,
+    #[serde(with = "crate::storage::wide")]
+    pub(crate) last_leaked: u128,
 }
 
 impl LeakyBucketInstance {
@@ -70,4 +198,117 @@ impl LeakyBucketInstance {
     pub fn last_leaked(&self) -> u128 {
         self.last_leaked
     }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        self.processed = rescaled(self.processed, limit, previous_limit);
+    }
+}
+
+#[test]
+fn leaky_bucket_shaping() {
+    use crate::types::SerializableInstance;
+
+    let bucket = LeakyBucket::new(2, Duration::from_millis(100))
+        .with_max_queue_wait(Duration::from_millis(250));
+    let mut instance = None;
+    let ts = 1000u128;
+
+    // 2 immediately, then queued 100ms apart until the wait would exceed 250ms
+    let mut delays = vec![];
+    for _ in 0..5 {
+        match bucket.is_rate_limited_now(ts, instance.clone()) {
+            Ok(i) => {
+                let LimiterInstance::LeakyBucketInstance(ref inner) = i else {
+                    unreachable!()
+                };
+                delays.push(bucket.delay_now(ts, inner).as_millis());
+                instance = Some(i.to_bytes().unwrap());
+            }
+            Err(e) => assert!(matches!(e, RateLimiterError::RateExceeded)),
+        }
+    }
+    assert_eq!(delays, vec![0, 0, 100, 200]);
+
+    // the bucket leaks as usual, making room for queued requests
+    let result = bucket
+        .is_rate_limited_now(ts + 100, instance.clone())
+        .unwrap();
+    let i = result.as_leaky_bucket_instance().unwrap();
+    assert_eq!(bucket.delay_now(ts + 100, &i).as_millis(), 200);
+
+    // without a queue, a full bucket rejects
+    let meter = LeakyBucket::new(2, Duration::from_millis(100));
+    assert!(meter.is_rate_limited_now(ts, instance).is_err());
+}
+
+#[test]
+fn leaky_bucket_steady_traffic() {
+    use crate::types::SerializableInstance;
+
+    let bucket = LeakyBucket::new(5, Duration::from_millis(100));
+    let mut instance = None;
+
+    // a request every 30ms, more often than the bucket leaks
+    let mut allowed = 0;
+    for ts in (0..3000u128).step_by(30) {
+        if let Ok(i) = bucket.is_rate_limited_now(ts, instance.clone()) {
+            allowed += 1;
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+    // the bucket's capacity plus one request per elapsed leak, (3000 - 30) / 100
+    assert_eq!(allowed, 5 + 29);
+
+    let i = bucket
+        .window_instance(instance.clone().unwrap())
+        .unwrap()
+        .as_leaky_bucket_instance()
+        .unwrap();
+    assert_eq!(i.processed(), 5);
+    assert_eq!(i.last_leaked(), 2900);
+
+    // partial progress counts towards the queueing delay
+    let shaping = bucket
+        .clone()
+        .with_max_queue_wait(Duration::from_millis(1000));
+    let result = shaping.is_rate_limited_now(2970, instance).unwrap();
+    let i = result.as_leaky_bucket_instance().unwrap();
+    assert_eq!(shaping.delay_now(2970, &i).as_millis(), 30);
+
+    // an idle bucket empties completely and starts over
+    let result = bucket
+        .is_rate_limited_now(
+            10_000,
+            Some(LimiterInstance::LeakyBucketInstance(i).to_bytes().unwrap()),
+        )
+        .unwrap();
+    let i = result.as_leaky_bucket_instance().unwrap();
+    assert_eq!((i.processed(), i.last_leaked()), (1, 10_000));
+}
+
+#[test]
+fn leaky_bucket_leak_amount() {
+    use crate::types::SerializableInstance;
+
+    // 3 requests per second, a leak every 333.3ms
+    let bucket = LeakyBucket::new(3, Duration::from_secs(1)).with_leak_amount(3);
+    let mut instance = None;
+    let mut allowed = 0;
+    for ts in 0..10_000u128 {
+        if let Ok(i) = bucket.is_rate_limited_now(ts, instance.clone()) {
+            allowed += 1;
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+    // the bucket's capacity plus the 29 leaks by 9999ms, a 333ms frequency would leak 30
+    assert_eq!(allowed, 3 + 29);
+
+    // more than one leak per millisecond
+    let bucket = LeakyBucket::new(1, Duration::from_secs(1)).with_leak_amount(2000);
+    assert!(bucket.validate().is_ok());
+    let mut instance = None;
+    for ts in 0..10u128 {
+        let i = bucket.is_rate_limited_now(ts, instance).unwrap();
+        instance = Some(i.to_bytes().unwrap());
+    }
 }
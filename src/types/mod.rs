@@ -1,24 +1,189 @@
+pub mod adaptive;
+pub mod any;
+pub mod calendar;
+pub mod concurrency;
 pub mod fixed_window;
 pub mod leaky_bucket;
 pub mod sliding_window;
+pub mod sliding_window_log;
 pub mod token_bucket;
 
 use crate::backend::BackendError;
+use adaptive::AdaptiveInstance;
+use concurrency::ConcurrencyInstance;
 use fixed_window::FixedWindowInstance;
 use leaky_bucket::LeakyBucketInstance;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sliding_window::SlidingWindowInstance;
+use sliding_window_log::SlidingWindowLogInstance;
 use std::{
     error::Error,
     fmt::{self, Debug, Display},
+    sync::Arc,
+    time::Duration,
 };
 use token_bucket::TokenBucketInstance;
 
-pub trait LimiterType: Clone {
+pub trait LimiterType {
     fn is_ratelimited(&self, value: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError>;
     fn window_instance(&self, value: Vec<u8>) -> Result<LimiterInstance, RateLimiterError> {
         LimiterInstance::from_bytes(value)
     }
+
+    /// Takes the lease identified by `lease`. Only called when `holds_leases` returns `true`.
+    fn acquire(
+        &self,
+        value: Option<Vec<u8>>,
+        _lease: u64,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        self.is_ratelimited(value)
+    }
+
+    /// Gives back the lease identified by `lease`. `None` means there's nothing to write back.
+    fn release(
+        &self,
+        _value: Vec<u8>,
+        _lease: u64,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        Ok(None)
+    }
+
+    /// How long the request that produced `instance` has to wait before proceeding. Only shaping
+    /// limiters (see `LeakyBucket::with_max_queue_wait`) return a non-zero delay.
+    fn delay(&self, _instance: &LimiterInstance) -> Duration {
+        Duration::ZERO
+    }
+
+    /// Adapts the instance to feedback about a request it let through. `None` means there's nothing to
+    /// write back.
+    fn report(
+        &self,
+        _value: Vec<u8>,
+        _outcome: &Outcome,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        Ok(None)
+    }
+
+    /// Whether the limiter adapts to `RateLimiter::report`, which is a no-op otherwise.
+    fn wants_feedback(&self) -> bool {
+        false
+    }
+
+    /// Whether requests hold on to what they consume until they're done (see `RateLimiter::acquire`).
+    fn holds_leases(&self) -> bool {
+        false
+    }
+
+    /// Identifies the algorithm in stored values (see `storage::Envelope`) and migrations, so it
+    /// must stay the same across releases. Built-in limiters use their snake case name, ex:
+    /// `"token_bucket"`.
+    fn name(&self) -> &str;
+
+    /// Number of requests the limiter admits at once (threshold, capacity...), used to rescale stored
+    /// usage when it changes (see `ConfigChangePolicy::Rescale`). `None` if it has no such limit.
+    fn limit(&self) -> Option<u32> {
+        None
+    }
+
+    /// Requests `instance` still admits, ex: to report it in response headers. `None` if the limiter
+    /// can't tell from the instance alone.
+    fn remaining(&self, _instance: &LimiterInstance) -> Option<u32> {
+        None
+    }
+
+    /// Hash of the limiter's parameters, stored with its instances to tell when they change (see
+    /// `fingerprint`).
+    fn fingerprint(&self) -> u64 {
+        0
+    }
+
+    /// Checks the limiter's parameters, called by `RateLimiterBuilder::try_build`. Zero-length
+    /// windows for instance would divide by zero on the first request.
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        Ok(())
+    }
+}
+
+fn rescaled(value: u32, limit: u32, previous_limit: u32) -> u32 {
+    (value as u64 * limit as u64 / previous_limit as u64) as u32
+}
+
+// lets `Arc<dyn LimiterType>` be used as a limiter, see `DynRateLimiter`
+impl<T: LimiterType + ?Sized> LimiterType for Arc<T> {
+    fn is_ratelimited(&self, value: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        (**self).is_ratelimited(value)
+    }
+
+    fn window_instance(&self, value: Vec<u8>) -> Result<LimiterInstance, RateLimiterError> {
+        (**self).window_instance(value)
+    }
+
+    fn acquire(
+        &self,
+        value: Option<Vec<u8>>,
+        lease: u64,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        (**self).acquire(value, lease)
+    }
+
+    fn release(
+        &self,
+        value: Vec<u8>,
+        lease: u64,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        (**self).release(value, lease)
+    }
+
+    fn delay(&self, instance: &LimiterInstance) -> Duration {
+        (**self).delay(instance)
+    }
+
+    fn report(
+        &self,
+        value: Vec<u8>,
+        outcome: &Outcome,
+    ) -> Result<Option<LimiterInstance>, RateLimiterError> {
+        (**self).report(value, outcome)
+    }
+
+    fn wants_feedback(&self) -> bool {
+        (**self).wants_feedback()
+    }
+
+    fn holds_leases(&self) -> bool {
+        (**self).holds_leases()
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn limit(&self) -> Option<u32> {
+        (**self).limit()
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        (**self).remaining(instance)
+    }
+
+    fn fingerprint(&self) -> u64 {
+        (**self).fingerprint()
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        (**self).validate()
+    }
+}
+
+/// FNV-1a hash of `params`, stable across builds and platforms so that every process sharing a
+/// backend computes the same fingerprint.
+pub fn fingerprint(params: &[u128]) -> u64 {
+    params
+        .iter()
+        .flat_map(|p| p.to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +192,14 @@ pub enum LimiterInstance {
     SlidingWindowInstance(SlidingWindowInstance),
     TokenBucketInstance(TokenBucketInstance),
     LeakyBucketInstance(LeakyBucketInstance),
+    SlidingWindowLogInstance(SlidingWindowLogInstance),
+    ConcurrencyInstance(ConcurrencyInstance),
+    AdaptiveInstance(AdaptiveInstance),
+    /// Instance of a `LimiterType` defined outside of this crate, see `LimiterInstance::custom`.
+    Custom {
+        type_id: String,
+        bytes: Vec<u8>,
+    },
 }
 
 impl LimiterInstance {
@@ -57,6 +230,70 @@ impl LimiterInstance {
             _ => Err(RateLimiterError::WrongLimiterInstanceType),
         }
     }
+
+    pub fn as_sliding_window_log_instance(
+        self,
+    ) -> Result<SlidingWindowLogInstance, RateLimiterError> {
+        match self {
+            Self::SlidingWindowLogInstance(i) => Ok(i),
+            _ => Err(RateLimiterError::WrongLimiterInstanceType),
+        }
+    }
+
+    pub fn as_concurrency_instance(self) -> Result<ConcurrencyInstance, RateLimiterError> {
+        match self {
+            Self::ConcurrencyInstance(i) => Ok(i),
+            _ => Err(RateLimiterError::WrongLimiterInstanceType),
+        }
+    }
+
+    pub fn as_adaptive_instance(self) -> Result<AdaptiveInstance, RateLimiterError> {
+        match self {
+            Self::AdaptiveInstance(i) => Ok(i),
+            _ => Err(RateLimiterError::WrongLimiterInstanceType),
+        }
+    }
+
+    /// Wraps the instance of a user-defined `LimiterType`. `type_id` identifies the algorithm, so that
+    /// instances stored by a different one are detected (and discarded, see `with_discard_invalid_cache_entries`).
+    pub fn custom<I: Serialize>(type_id: &str, instance: &I) -> Result<Self, RateLimiterError> {
+        Ok(Self::Custom {
+            type_id: type_id.to_string(),
+            bytes: bincode::serialize(instance).map_err(RateLimiterError::MalformedValue)?,
+        })
+    }
+
+    // scales usage recorded under a limit of `previous_limit` to `limit`
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        match self {
+            Self::FixedWindowInstance(i) => i.rescale(limit, previous_limit),
+            Self::SlidingWindowInstance(i) => i.rescale(limit, previous_limit),
+            Self::TokenBucketInstance(i) => i.rescale(limit, previous_limit),
+            Self::LeakyBucketInstance(i) => i.rescale(limit, previous_limit),
+            Self::SlidingWindowLogInstance(i) => i.rescale(limit, previous_limit),
+            Self::AdaptiveInstance(i) => i.rescale(limit, previous_limit),
+            // in-flight leases can't be scaled, and custom instances are opaque
+            Self::ConcurrencyInstance(_) | Self::Custom { .. } => {}
+        }
+    }
+
+    pub fn as_custom<I: DeserializeOwned>(self, type_id: &str) -> Result<I, RateLimiterError> {
+        match self {
+            Self::Custom { type_id: t, bytes } if t == type_id => {
+                bincode::deserialize(&bytes).map_err(RateLimiterError::MalformedValue)
+            }
+            _ => Err(RateLimiterError::WrongLimiterInstanceType),
+        }
+    }
+}
+
+/// Feedback about a request that was let through, see `RateLimiter::report`.
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// Counts as a failure if it's above the limiter's latency threshold, as a success otherwise.
+    Latency(Duration),
 }
 
 impl SerializableInstance for LimiterInstance {}
@@ -67,8 +304,8 @@ pub(crate) trait SerializableInstance:
     fn from_bytes(bytes: Vec<u8>) -> Result<Self, RateLimiterError> {
         bincode::deserialize(&bytes).map_err(RateLimiterError::MalformedValue)
     }
-    fn to_bytes(self) -> Result<Vec<u8>, RateLimiterError> {
-        bincode::serialize(&self).map_err(RateLimiterError::MalformedValue)
+    fn to_bytes(&self) -> Result<Vec<u8>, RateLimiterError> {
+        bincode::serialize(self).map_err(RateLimiterError::MalformedValue)
     }
 }
 
@@ -96,3 +333,32 @@ impl Display for RateLimiterError {
 }
 
 impl Error for RateLimiterError {}
+
+/// A limiter parameter the limiter can't work with, see `LimiterType::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidParameter {
+    pub parameter: &'static str,
+    pub reason: &'static str,
+}
+
+impl InvalidParameter {
+    pub fn new(parameter: &'static str, reason: &'static str) -> Self {
+        InvalidParameter { parameter, reason }
+    }
+}
+
+impl Display for InvalidParameter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.parameter, self.reason)
+    }
+}
+
+impl Error for InvalidParameter {}
+
+// instances are timestamped in milliseconds, shorter durations round down to zero
+fn check_duration(parameter: &'static str, duration: Duration) -> Result<(), InvalidParameter> {
+    if duration.as_millis() == 0 {
+        return Err(InvalidParameter::new(parameter, "must be at least 1ms"));
+    }
+    Ok(())
+}
@@ -1,7 +1,11 @@
-use super::{fixed_window::FixedWindowInstance, LimiterInstance, LimiterType, RateLimiterError};
+use super::{
+    check_duration, fingerprint,
+    fixed_window::{FixedWindowInstance, WindowAlignment},
+    InvalidParameter, LimiterInstance, LimiterType, RateLimiterError,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    cmp,
+    cmp, mem,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -9,6 +13,7 @@ use std::{
 pub struct SlidingWindowCounter {
     threshold: u32,
     window_length: Duration,
+    alignment: WindowAlignment,
 }
 
 impl SlidingWindowCounter {
@@ -16,17 +21,56 @@ impl SlidingWindowCounter {
         SlidingWindowCounter {
             threshold,
             window_length,
+            alignment: WindowAlignment::FirstRequest,
         }
     }
+
+    pub fn with_alignment(mut self, alignment: WindowAlignment) -> Self {
+        self.alignment = alignment;
+        self
+    }
 }
 
 impl LimiterType for SlidingWindowCounter {
     fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis();
-        self.is_rate_limited_now(now, bytes)
+        self.is_rate_limited_now(now(), bytes)
+    }
+
+    fn name(&self) -> &str {
+        "sliding_window_counter"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.threshold)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::SlidingWindowInstance(i) => {
+                let mut instance = SlidingWindowInstance {
+                    current: FixedWindowInstance::new(i.current.window_start(), i.current.count),
+                    previous: FixedWindowInstance::new(i.previous.window_start(), i.previous.count),
+                };
+                let now = now();
+                self.roll(now, &mut instance);
+                // requests are allowed while the weighted count is below the threshold
+                let left = (self.threshold as f64 - self.weighted_count(now, &instance)).ceil();
+                Some(left.max(0.0) as u32)
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[
+            self.threshold as u128,
+            self.window_length.as_millis(),
+            self.alignment as u128,
+        ])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("window_length", self.window_length)
     }
 }
 
@@ -36,38 +80,75 @@ impl SlidingWindowCounter {
         now: u128,
         bytes: Option<Vec<u8>>,
     ) -> Result<LimiterInstance, RateLimiterError> {
+        let window_length = self.window_length.as_millis();
         let mut instance = match bytes {
             Some(b) => self.window_instance(b)?.as_sliding_window_instance()?,
-            None => SlidingWindowInstance {
-                current: FixedWindowInstance::new(now, 0),
-                previous: FixedWindowInstance::new(now, 0),
-            },
+            None => {
+                let start = self
+                    .alignment
+                    .window_start(now, window_length)
+                    .unwrap_or(now);
+                SlidingWindowInstance {
+                    current: FixedWindowInstance::new(start, 0),
+                    previous: FixedWindowInstance::new(start.saturating_sub(window_length), 0),
+                }
+            }
         };
 
-        if instance.current.window_start() + self.window_length.as_millis() < now {
-            instance.previous = instance.current;
-            instance.current = FixedWindowInstance::new(now, 0)
+        self.roll(now, &mut instance);
+        if self.weighted_count(now, &instance) >= self.threshold as f64 {
+            return Err(RateLimiterError::RateExceeded);
         }
 
+        instance.current.count += 1;
+        Ok(LimiterInstance::SlidingWindowInstance(instance))
+    }
+
+    // moves the instance to the window `now` falls in
+    fn roll(&self, now: u128, instance: &mut SlidingWindowInstance) {
+        let window_length = self.window_length.as_millis();
+        match self.alignment.window_start(now, window_length) {
+            Some(start) if instance.current.window_start() != start => {
+                let current =
+                    mem::replace(&mut instance.current, FixedWindowInstance::new(start, 0));
+                // the previous window only counts if it directly precedes the current one
+                instance.previous = if current.window_start() + window_length == start {
+                    current
+                } else {
+                    FixedWindowInstance::new(start - window_length, 0)
+                };
+            }
+            Some(_) => {}
+            None => {
+                if instance.current.window_start() + window_length < now {
+                    instance.previous =
+                        mem::replace(&mut instance.current, FixedWindowInstance::new(now, 0));
+                }
+            }
+        }
+    }
+
+    // requests in the window ending at `now`, the previous window's weighted by how much of it is in
+    fn weighted_count(&self, now: u128, instance: &SlidingWindowInstance) -> f64 {
         let start = cmp::max(0, now - self.window_length.as_millis());
         let prev_end = instance.previous.window_start() + self.window_length.as_millis();
         let weight: f64 = cmp::max(0, prev_end as i64 - start as i64) as f64
             / self.window_length.as_millis() as f64;
-        let count = (instance.previous.window_count() as f64 * weight)
-            + instance.current.window_count() as f64;
-        if count >= self.threshold as f64 {
-            return Err(RateLimiterError::RateExceeded);
-        }
-
-        instance.current.count += 1;
-        Ok(LimiterInstance::SlidingWindowInstance(instance))
+        (instance.previous.window_count() as f64 * weight) + instance.current.window_count() as f64
     }
 }
 
+fn now() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis()
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SlidingWindowInstance {
-    current: FixedWindowInstance,
-    previous: FixedWindowInstance,
+    pub(crate) current: FixedWindowInstance,
+    pub(crate) previous: FixedWindowInstance,
 }
 
 impl SlidingWindowInstance {
@@ -78,6 +159,11 @@ impl SlidingWindowInstance {
     pub fn previous_window(&self) -> &FixedWindowInstance {
         &self.previous
     }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        self.current.rescale(limit, previous_limit);
+        self.previous.rescale(limit, previous_limit);
+    }
 }
 
 #[test]
@@ -121,3 +207,34 @@ fn sliding_window_counter() {
         };
     }
 }
+
+#[test]
+fn sliding_window_counter_aligned() {
+    use crate::types::SerializableInstance;
+
+    let counter = SlidingWindowCounter::new(4, Duration::from_millis(100))
+        .with_alignment(WindowAlignment::Epoch);
+    let mut instance = None;
+
+    // fill the window starting at 1000
+    for _ in 0..4 {
+        let result = counter.is_rate_limited_now(1010, instance);
+        instance = Some(result.unwrap().to_bytes().unwrap());
+    }
+
+    // at 1150 half of the previous window overlaps, 2 of its requests still count
+    for i in 0..3 {
+        let result = counter.is_rate_limited_now(1150, instance.clone());
+        assert!(result.is_ok() == (i < 2));
+        if let Ok(i) = result {
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+
+    // skipping a whole window forgets both
+    let result = counter.is_rate_limited_now(1310, instance).unwrap();
+    let instance = result.as_sliding_window_instance().unwrap();
+    assert_eq!(instance.current_window().window_start(), 1300);
+    assert_eq!(instance.previous_window().window_start(), 1200);
+    assert_eq!(instance.previous_window().window_count(), 0);
+}
@@ -0,0 +1,156 @@
+use super::{
+    check_duration, fingerprint, rescaled, InvalidParameter, LimiterInstance, LimiterType,
+    RateLimiterError,
+};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct SlidingWindowLog {
+    threshold: u32,
+    window_length: Duration,
+}
+
+impl SlidingWindowLog {
+    pub fn new(threshold: u32, window_length: Duration) -> Self {
+        SlidingWindowLog {
+            threshold,
+            window_length,
+        }
+    }
+}
+
+impl LimiterType for SlidingWindowLog {
+    fn is_ratelimited(&self, bytes: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.is_rate_limited_now(now, bytes)
+    }
+
+    fn name(&self) -> &str {
+        "sliding_window_log"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.threshold)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::SlidingWindowLogInstance(i) => {
+                Some(self.threshold.saturating_sub(i.count()))
+            }
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[self.threshold as u128, self.window_length.as_millis()])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("window_length", self.window_length)
+    }
+}
+
+impl SlidingWindowLog {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let mut instance = match bytes {
+            Some(b) => self.window_instance(b)?.as_sliding_window_log_instance()?,
+            None => SlidingWindowLogInstance { entries: vec![] },
+        };
+
+        // drop every entry that fell out of the window ending at `now`
+        let window_start = now.saturating_sub(self.window_length.as_millis());
+        instance.entries.retain(|(ts, _)| *ts > window_start);
+
+        if instance.count() >= self.threshold {
+            return Err(RateLimiterError::RateExceeded);
+        }
+
+        // requests sharing a timestamp are compacted into a single entry
+        match instance.entries.last_mut() {
+            Some((ts, count)) if *ts == now => *count += 1,
+            _ => instance.entries.push((now, 1)),
+        }
+        Ok(LimiterInstance::SlidingWindowLogInstance(instance))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SlidingWindowLogInstance {
+    #[serde(with = "crate::storage::wide::first")]
+    pub(crate) entries: Vec<(u128, u32)>,
+}
+
+impl SlidingWindowLogInstance {
+    /// Logged requests as `(timestamp, count)` pairs, oldest first.
+    pub fn entries(&self) -> &[(u128, u32)] {
+        &self.entries
+    }
+
+    /// Number of requests logged in the current window.
+    pub fn count(&self) -> u32 {
+        self.entries.iter().map(|(_, c)| c).sum()
+    }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        for (_, count) in self.entries.iter_mut() {
+            *count = rescaled(*count, limit, previous_limit);
+        }
+        self.entries.retain(|(_, count)| *count > 0);
+    }
+}
+
+#[test]
+fn sliding_window_log() {
+    use crate::types::SerializableInstance;
+
+    let mut instance = None;
+    let log = SlidingWindowLog::new(5, Duration::from_millis(100));
+    let mut ts = 1000u128;
+
+    // two requests in the same millisecond share an entry
+    for _ in 0..2 {
+        let result = log.is_rate_limited_now(ts, instance);
+        instance = Some(result.unwrap().to_bytes().unwrap());
+    }
+    ts += 40;
+    for _ in 0..3 {
+        let result = log.is_rate_limited_now(ts, instance);
+        instance = Some(result.unwrap().to_bytes().unwrap());
+        ts += 10;
+    }
+
+    // 5 requests within the last 100ms, should fail
+    let result = log.is_rate_limited_now(ts, instance.clone());
+    assert!(result.is_err());
+
+    // the first two requests leave the window at exactly 1100
+    ts = 1099;
+    assert!(log.is_rate_limited_now(ts, instance.clone()).is_err());
+    ts = 1100;
+    for i in 0..3 {
+        let result = log.is_rate_limited_now(ts, instance.clone());
+        assert!(result.is_ok() == (i < 2));
+        instance = match result {
+            Ok(i) => Some(i.to_bytes().unwrap()),
+            Err(_) => instance.clone(),
+        };
+    }
+
+    let instance = log
+        .window_instance(instance.unwrap())
+        .unwrap()
+        .as_sliding_window_log_instance()
+        .unwrap();
+    assert_eq!(instance.count(), 5);
+    assert_eq!(instance.entries().len(), 4);
+    assert_eq!(instance.entries().last(), Some(&(1100, 2)));
+}
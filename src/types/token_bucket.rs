@@ -1,20 +1,48 @@
-use super::{LimiterInstance, LimiterType, RateLimiterError};
+use super::{
+    check_duration, fingerprint, InvalidParameter, LimiterInstance, LimiterType, RateLimiterError,
+};
 use serde::{Deserialize, Serialize};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    cmp,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Tokens are stored as integer multiples of `1 / TOKEN_SCALE` of a token.
+pub const TOKEN_SCALE: u64 = 1_000_000;
 
 #[derive(Debug, Clone)]
 pub struct TokenBucket {
     capacity: u32,
-    fill_frequency: Duration, // 1 token per fill_frequency
+    fill_frequency: Duration, // refill_amount tokens per fill_frequency
+    refill_amount: u32,
+    initial_tokens: u32,
 }
 
 impl TokenBucket {
+    /// A full bucket of `capacity` tokens, refilled by 1 token every `fill_frequency`.
     pub fn new(capacity: u32, fill_frequency: Duration) -> Self {
         TokenBucket {
             capacity,
             fill_frequency,
+            refill_amount: 1,
+            initial_tokens: capacity,
         }
     }
+
+    /// Refill `amount` tokens every `fill_frequency` instead of 1.
+    ///
+    /// Tokens are added continuously, `TokenBucket::new(50, Duration::from_secs(3600)).with_refill_amount(1000)`
+    /// allows 1000 requests per hour with bursts of up to 50.
+    pub fn with_refill_amount(mut self, amount: u32) -> Self {
+        self.refill_amount = amount;
+        self
+    }
+
+    /// Number of tokens a new bucket starts with. Defaults to `capacity`, capped at `capacity`.
+    pub fn with_initial_tokens(mut self, tokens: u32) -> Self {
+        self.initial_tokens = tokens;
+        self
+    }
 }
 
 impl LimiterType for TokenBucket {
@@ -23,48 +51,160 @@ impl LimiterType for TokenBucket {
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis();
+        self.is_rate_limited_now(now, bytes)
+    }
+
+    fn name(&self) -> &str {
+        "token_bucket"
+    }
+
+    fn limit(&self) -> Option<u32> {
+        Some(self.capacity)
+    }
+
+    fn remaining(&self, instance: &LimiterInstance) -> Option<u32> {
+        match instance {
+            LimiterInstance::TokenBucketInstance(i) => Some(i.tokens()),
+            _ => None,
+        }
+    }
+
+    fn fingerprint(&self) -> u64 {
+        fingerprint(&[
+            self.capacity as u128,
+            self.fill_frequency.as_millis(),
+            self.refill_amount as u128,
+            self.initial_tokens as u128,
+        ])
+    }
+
+    fn validate(&self) -> Result<(), InvalidParameter> {
+        check_duration("fill_frequency", self.fill_frequency)?;
+        if self.refill_amount == 0 {
+            return Err(InvalidParameter::new(
+                "refill_amount",
+                "must be greater than zero",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TokenBucket {
+    fn is_rate_limited_now(
+        &self,
+        now: u128,
+        bytes: Option<Vec<u8>>,
+    ) -> Result<LimiterInstance, RateLimiterError> {
+        let capacity = self.capacity as u64 * TOKEN_SCALE;
         let mut instance = match bytes {
             Some(b) => self.window_instance(b)?.as_token_bucket_instance()?,
             None => TokenBucketInstance {
-                tokens: self.capacity as f32,
+                tokens: cmp::min(self.initial_tokens, self.capacity) as u64 * TOKEN_SCALE,
                 last_access: now,
             },
         };
 
-        let elapsed = now - instance.last_access();
+        let elapsed = now.saturating_sub(instance.last_access());
+        let fill_frequency = self.fill_frequency.as_millis();
+        let rate = self.refill_amount as u128 * TOKEN_SCALE as u128; // per fill_frequency
+        let added = elapsed * rate / fill_frequency;
 
-        instance.tokens += elapsed as f32 / self.fill_frequency.as_millis() as f32;
-        if instance.tokens > self.capacity as f32 {
-            instance.tokens = self.capacity as f32;
+        if instance.tokens as u128 + added >= capacity as u128 {
+            instance.tokens = capacity;
+            instance.last_access = now;
+        } else if added > 0 {
+            instance.tokens += added as u64;
+            // only move forward by the time that was turned into tokens, so that
+            // frequent callers don't throw away partial refills
+            instance.last_access += (added * fill_frequency).div_ceil(rate);
         }
 
-        if instance.tokens < 1f32 {
+        if instance.tokens < TOKEN_SCALE {
             return Err(RateLimiterError::RateExceeded);
         }
-        instance.tokens -= 1f32;
-        instance.last_access = now;
+        instance.tokens -= TOKEN_SCALE;
         Ok(LimiterInstance::TokenBucketInstance(instance))
     }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TokenBucketInstance {
-    tokens: f32,
-    last_access: u128,
+    #[serde(with = "crate::storage::wide")]
+    pub(crate) tokens: u64,
+    #[serde(with = "crate::storage::wide")]
+    pub(crate) last_access: u128,
 }
 
 impl TokenBucketInstance {
-    pub fn new(last_access: u128, tokens: f32) -> Self {
+    pub fn new(last_access: u128, tokens: u32) -> Self {
         TokenBucketInstance {
             last_access,
-            tokens,
+            tokens: tokens as u64 * TOKEN_SCALE,
         }
     }
-    pub fn tokens(&self) -> f32 {
+
+    /// Whole tokens available.
+    pub fn tokens(&self) -> u32 {
+        (self.tokens / TOKEN_SCALE) as u32
+    }
+
+    /// Available tokens in units of `1 / TOKEN_SCALE` of a token.
+    pub fn scaled_tokens(&self) -> u64 {
         self.tokens
     }
 
     pub fn last_access(&self) -> u128 {
         self.last_access
     }
+
+    pub(crate) fn rescale(&mut self, limit: u32, previous_limit: u32) {
+        self.tokens = (self.tokens as u128 * limit as u128 / previous_limit as u128) as u64;
+    }
+}
+
+#[test]
+fn token_bucket() {
+    use crate::types::SerializableInstance;
+
+    // 1000 tokens per hour, burst of 50, starting empty
+    let bucket = TokenBucket::new(50, Duration::from_secs(3600))
+        .with_refill_amount(1000)
+        .with_initial_tokens(0);
+    let mut ts = 0u128;
+
+    let result = bucket.is_rate_limited_now(ts, None);
+    assert!(result.is_err());
+
+    // a token every 3.6s, polled every millisecond
+    let mut instance = Some(
+        LimiterInstance::TokenBucketInstance(TokenBucketInstance::new(ts, 0))
+            .to_bytes()
+            .unwrap(),
+    );
+    let mut allowed = 0;
+    while ts < 36_000 {
+        ts += 1;
+        if let Ok(i) = bucket.is_rate_limited_now(ts, instance.clone()) {
+            allowed += 1;
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+    assert_eq!(allowed, 10);
+
+    // refills are capped at capacity
+    ts += 3_600_000;
+    for i in 0..51 {
+        let result = bucket.is_rate_limited_now(ts, instance.clone());
+        assert!(result.is_ok() == (i < 50));
+        if let Ok(i) = result {
+            instance = Some(i.to_bytes().unwrap());
+        }
+    }
+
+    // precision doesn't degrade at large capacities
+    let bucket = TokenBucket::new(u32::MAX, Duration::from_secs(1));
+    let result = bucket.is_rate_limited_now(ts, None).unwrap();
+    let instance = result.as_token_bucket_instance().unwrap();
+    assert_eq!(instance.tokens(), u32::MAX - 1);
 }
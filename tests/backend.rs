@@ -13,7 +13,13 @@ fn memcache() {
 
     let cache = memcache::connect("memcache://127.0.0.1:11211").unwrap();
     let backend = MemCache::new(cache);
-    test_backend(backend);
+    test_backend(backend.clone());
+
+    // keys memcached would reject are encoded
+    let agent = format!("Mozilla/5.0 (X11; Linux x86_64) {}", "x".repeat(300));
+    backend.set(&agent, &[1], None).unwrap();
+    assert_eq!(backend.get(&agent).unwrap().0, [1]);
+    backend.delete(&agent).unwrap();
 }
 
 #[cfg(feature = "redis")]
@@ -0,0 +1,139 @@
+use brakes::{
+    backend::local::Memory,
+    config::RateLimiterConfig,
+    hierarchy::HierarchicalRateLimiter,
+    types::{
+        adaptive::Adaptive,
+        any::AnyLimiter,
+        calendar::{CalendarWindow, Period},
+        concurrency::ConcurrencyLimiter,
+        fixed_window::FixedWindow,
+        leaky_bucket::LeakyBucket,
+        sliding_window::SlidingWindowCounter,
+        sliding_window_log::SlidingWindowLog,
+        token_bucket::TokenBucket,
+        InvalidParameter,
+    },
+    BuildError, RateLimiter,
+};
+use std::time::Duration;
+
+fn try_build(limiter: impl Into<AnyLimiter>) -> Result<(), BuildError> {
+    RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(limiter.into())
+        .try_build()
+        .map(|_| ())
+}
+
+#[test]
+fn missing() {
+    let result = RateLimiter::<FixedWindow, Memory>::builder()
+        .with_limiter(FixedWindow::new(10, Duration::from_secs(1)))
+        .try_build();
+    assert_eq!(result.unwrap_err(), BuildError::MissingBackend);
+
+    let result = RateLimiter::<FixedWindow, Memory>::builder()
+        .with_backend(Memory::new())
+        .try_build();
+    assert_eq!(result.unwrap_err(), BuildError::MissingLimiter);
+
+    let result = HierarchicalRateLimiter::builder()
+        .with_backend(Memory::new())
+        .try_build();
+    assert_eq!(result.unwrap_err(), BuildError::MissingLimiter);
+}
+
+#[test]
+fn invalid_parameters() {
+    let zero = Duration::ZERO;
+    let invalid = |limiter: &str, parameter, reason| {
+        Err(BuildError::InvalidLimiter(
+            limiter.to_string(),
+            InvalidParameter::new(parameter, reason),
+        ))
+    };
+    let too_short = "must be at least 1ms";
+
+    assert_eq!(
+        try_build(FixedWindow::new(10, zero)),
+        invalid("fixed_window", "window_length", too_short)
+    );
+    assert_eq!(
+        try_build(SlidingWindowCounter::new(10, Duration::from_micros(10))),
+        invalid("sliding_window_counter", "window_length", too_short)
+    );
+    assert_eq!(
+        try_build(SlidingWindowLog::new(10, zero)),
+        invalid("sliding_window_log", "window_length", too_short)
+    );
+    assert_eq!(
+        try_build(TokenBucket::new(10, zero)),
+        invalid("token_bucket", "fill_frequency", too_short)
+    );
+    assert_eq!(
+        try_build(TokenBucket::new(10, Duration::from_secs(1)).with_refill_amount(0)),
+        invalid("token_bucket", "refill_amount", "must be greater than zero")
+    );
+    assert_eq!(
+        try_build(LeakyBucket::new(10, zero)),
+        invalid("leaky_bucket", "leak_frequency", too_short)
+    );
+    assert_eq!(
+        try_build(ConcurrencyLimiter::new(10, zero)),
+        invalid("concurrency", "lease_ttl", too_short)
+    );
+    assert_eq!(
+        try_build(CalendarWindow::new(10, Period::Every(zero))),
+        invalid("calendar_window", "period", too_short)
+    );
+    assert_eq!(
+        try_build(Adaptive::new(20, 10, Duration::from_secs(1))),
+        invalid("adaptive", "min_limit", "must not exceed max_limit")
+    );
+    assert_eq!(
+        try_build(Adaptive::new(0, 10, Duration::from_secs(1))),
+        invalid("adaptive", "min_limit", "must be greater than zero")
+    );
+    assert_eq!(
+        try_build(Adaptive::new(1, 10, Duration::from_secs(1)).with_decrease_factor(f64::NAN)),
+        invalid("adaptive", "decrease_factor", "must be between 0 and 1")
+    );
+
+    assert!(try_build(TokenBucket::new(10, Duration::from_millis(1))).is_ok());
+    assert!(try_build(CalendarWindow::new(10, Period::Month)).is_ok());
+
+    let result = HierarchicalRateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_level("user", FixedWindow::new(10, zero), |k| Some(k.to_string()))
+        .try_build();
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "invalid fixed_window: window_length must be at least 1ms"
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid leaky_bucket: leak_frequency must be at least 1ms")]
+fn build_panics() {
+    RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(LeakyBucket::new(10, Duration::ZERO))
+        .build();
+}
+
+#[test]
+fn invalid_config() {
+    let config: RateLimiterConfig = toml::from_str(
+        r#"
+        [limiter]
+        type = "token_bucket"
+        rate = "10/0s"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.build().err().unwrap().to_string(),
+        "invalid token_bucket: fill_frequency must be at least 1ms"
+    );
+}
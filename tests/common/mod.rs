@@ -0,0 +1,22 @@
+use brakes::backend::{Backend, BackendError};
+
+// a backend that is down
+pub struct Down;
+
+impl Backend for Down {
+    fn get(&self, _: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn set(&self, _: &str, _: &[u8], _: Option<u64>) -> Result<(), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn delete(&self, _: &str) -> Result<(), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn name(&self) -> &str {
+        "down"
+    }
+}
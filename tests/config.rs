@@ -0,0 +1,154 @@
+use brakes::{
+    config::{parse_duration, Rate, RateLimiterConfig},
+    types::LimiterType,
+    RetryStrategy,
+};
+use std::time::Duration;
+
+#[test]
+fn rate() {
+    let rate: Rate = "100/min".parse().unwrap();
+    assert_eq!(rate.amount, 100);
+    assert_eq!(rate.per, Duration::from_secs(60));
+
+    let rate: Rate = "5 / 30s".parse().unwrap();
+    assert_eq!(rate.amount, 5);
+    assert_eq!(rate.per, Duration::from_secs(30));
+
+    assert_eq!(parse_duration("1 hour").unwrap(), Duration::from_secs(3600));
+    assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+
+    for invalid in ["100", "0/min", "100/fortnight", "x/s"] {
+        assert!(invalid.parse::<Rate>().is_err());
+    }
+}
+
+#[test]
+fn token_bucket_from_toml() {
+    let config: RateLimiterConfig = toml::from_str(
+        r#"
+        on_failure = { retry_and_allow = 3 }
+        on_conflict = "deny"
+
+        [limiter]
+        type = "token_bucket"
+        rate = "100/min"
+        burst = 20
+        "#,
+    )
+    .unwrap();
+    assert!(matches!(
+        config.on_failure,
+        Some(RetryStrategy::RetryAndAllow(3))
+    ));
+    assert!(matches!(config.on_conflict, Some(RetryStrategy::Deny)));
+
+    let limiter = config.build().unwrap();
+    for i in 0..21 {
+        assert!(limiter.is_ratelimited("key").is_ok() == (i < 20));
+    }
+}
+
+#[test]
+fn unknown_keys() {
+    for invalid in [
+        // misspelled limiter setting
+        r#"
+        [limiter]
+        type = "token_bucket"
+        rate = "100/min"
+        brust = 20
+        "#,
+        // misspelled top-level setting
+        r#"
+        on_failuer = "deny"
+
+        [limiter]
+        type = "fixed_window"
+        rate = "100/min"
+        "#,
+    ] {
+        let result = toml::from_str::<RateLimiterConfig>(invalid);
+        assert!(
+            result.is_err_and(|e| e.to_string().contains("unknown field")),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn leaky_bucket_from_toml() {
+    // more than one leak per millisecond
+    let config: RateLimiterConfig = toml::from_str(
+        r#"
+        [limiter]
+        type = "leaky_bucket"
+        rate = "5000/s"
+        burst = 1
+        "#,
+    )
+    .unwrap();
+    let limiter = config.build().unwrap();
+    assert!(limiter.is_ratelimited("key").is_ok());
+    std::thread::sleep(Duration::from_millis(2));
+    assert!(limiter.is_ratelimited("key").is_ok());
+}
+
+#[test]
+fn limiters_from_toml() {
+    for (limiter, name) in [
+        (r#"type = "fixed_window""#, "fixed_window"),
+        (
+            r#"type = "sliding_window_counter"
+            alignment = "epoch""#,
+            "sliding_window_counter",
+        ),
+        (r#"type = "sliding_window_log""#, "sliding_window_log"),
+        (
+            r#"type = "leaky_bucket"
+            max_queue_wait = "1s""#,
+            "leaky_bucket",
+        ),
+    ] {
+        let config: RateLimiterConfig = toml::from_str(&format!(
+            "[limiter]\nrate = \"2/s\"\n{}\n[backend]\ntype = \"memory\"",
+            limiter
+        ))
+        .unwrap();
+        assert_eq!(config.limiter.build().unwrap().name(), name);
+    }
+
+    let config: RateLimiterConfig = toml::from_str(
+        r#"
+        codec = "compact"
+
+        [limiter]
+        type = "calendar_window"
+        limit = 1000
+        period = "day"
+        utc_offset_secs = 3600
+        "#,
+    )
+    .unwrap();
+    let limiter = config.build().unwrap();
+    assert!(limiter.is_ratelimited("key").is_ok());
+    assert_eq!(
+        limiter
+            .get_usage("key")
+            .unwrap()
+            .as_fixed_window_instance()
+            .unwrap()
+            .window_count(),
+        1
+    );
+
+    let invalid: Result<RateLimiterConfig, _> = toml::from_str(
+        r#"
+        [limiter]
+        type = "fixed_window"
+        rate = "100 per minute"
+        "#,
+    );
+    assert!(invalid.is_err());
+}
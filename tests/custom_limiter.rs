@@ -0,0 +1,73 @@
+use brakes::{
+    backend::local::Memory,
+    types::{fixed_window::FixedWindow, LimiterInstance, LimiterType, RateLimiterError},
+    RateLimiter,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// a quota that never resets
+#[derive(Clone)]
+struct Lifetime(u32);
+
+#[derive(Serialize, Deserialize)]
+struct LifetimeInstance {
+    used: u32,
+}
+
+impl LimiterType for Lifetime {
+    fn is_ratelimited(&self, value: Option<Vec<u8>>) -> Result<LimiterInstance, RateLimiterError> {
+        let mut instance = match value {
+            Some(v) => self
+                .window_instance(v)?
+                .as_custom::<LifetimeInstance>("lifetime")?,
+            None => LifetimeInstance { used: 0 },
+        };
+        if instance.used >= self.0 {
+            return Err(RateLimiterError::RateExceeded);
+        }
+        instance.used += 1;
+        LimiterInstance::custom("lifetime", &instance)
+    }
+
+    fn name(&self) -> &str {
+        "lifetime"
+    }
+}
+
+#[test]
+fn custom_limiter() {
+    let backend = Memory::new();
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(Lifetime(2))
+        .build();
+
+    for i in 0..3 {
+        assert!(limiter.is_ratelimited("ip").is_ok() == (i < 2));
+    }
+    let usage = limiter
+        .get_usage("ip")
+        .unwrap()
+        .as_custom::<LifetimeInstance>("lifetime")
+        .unwrap();
+    assert_eq!(usage.used, 2);
+
+    // custom instances are validated like built-in ones
+    let fixed_window = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(1)))
+        .with_discard_invalid_cache_entries(false)
+        .build();
+    assert!(matches!(
+        fixed_window.is_ratelimited("ip"),
+        Err(RateLimiterError::WrongLimiterInstanceType)
+    ));
+    assert!(matches!(
+        fixed_window
+            .get_usage("ip")
+            .unwrap()
+            .as_custom::<LifetimeInstance>("other"),
+        Err(RateLimiterError::WrongLimiterInstanceType)
+    ));
+}
@@ -0,0 +1,70 @@
+use brakes::{
+    backend::{local::Memory, Backend},
+    types::{
+        any::AnyLimiter, concurrency::ConcurrencyLimiter, fixed_window::FixedWindow,
+        token_bucket::TokenBucket, LimiterType,
+    },
+    DynRateLimiter, RateLimiter,
+};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+#[test]
+fn dyn_limiters() {
+    let backend = Memory::new();
+    let mut limiters: HashMap<&str, DynRateLimiter> = HashMap::new();
+    limiters.insert(
+        "login",
+        RateLimiter::builder()
+            .with_backend(backend.clone())
+            .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+            .build()
+            .into_dyn(),
+    );
+    limiters.insert(
+        "api",
+        RateLimiter::builder()
+            .with_backend(backend.clone())
+            .with_limiter(TokenBucket::new(3, Duration::from_secs(60)))
+            .build()
+            .into_dyn(),
+    );
+
+    // picked at runtime
+    let limiter: Arc<dyn LimiterType + Send + Sync> =
+        Arc::new(ConcurrencyLimiter::new(1, Duration::from_secs(60)));
+    let backend: Arc<dyn Backend + Send + Sync> = Arc::new(backend);
+    limiters.insert(
+        "uploads",
+        RateLimiter::builder()
+            .with_backend(backend)
+            .with_limiter(limiter)
+            .build(),
+    );
+
+    for i in 0..2 {
+        assert!(limiters["login"].is_ratelimited("login:ip").is_ok() == (i < 1));
+    }
+    for i in 0..4 {
+        assert!(limiters["api"].is_ratelimited("api:ip").is_ok() == (i < 3));
+    }
+
+    // leases still work through the erased limiter
+    let lease = limiters["uploads"].acquire("uploads:ip").unwrap();
+    assert!(limiters["uploads"].acquire("uploads:ip").is_err());
+    drop(lease);
+    assert!(limiters["uploads"].acquire("uploads:ip").is_ok());
+}
+
+#[test]
+fn any_limiter() {
+    let limiter: AnyLimiter = TokenBucket::new(2, Duration::from_secs(60)).into();
+    assert_eq!(limiter.name(), "token_bucket");
+
+    let rate_limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(limiter)
+        .build();
+    for i in 0..3 {
+        assert!(rate_limiter.is_ratelimited("ip").is_ok() == (i < 2));
+    }
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use brakes::{
+    backend::{local::Memory, Backend, BackendError},
+    events::{Fallback, Verdict},
+    types::{fixed_window::FixedWindow, token_bucket::TokenBucket, RateLimiterError},
+    RateLimiter, RetryStrategy,
+};
+use common::Down;
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Event {
+    key: String,
+    limiter: String,
+    verdict: Verdict,
+    fallback: Option<Fallback>,
+    retries: u32,
+}
+
+type Events = Arc<Mutex<Vec<Event>>>;
+
+fn limiter<B: Backend>(backend: B, events: &Events) -> RateLimiter<FixedWindow, B> {
+    let events = events.clone();
+    RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_failure_strategy(RetryStrategy::RetryAndAllow(2))
+        .with_conflict_strategy(RetryStrategy::RetryAndDeny(1))
+        .on_decision(move |decision| {
+            assert!(decision.latency >= decision.backend_latency);
+            events.lock().unwrap().push(Event {
+                key: decision.key.to_string(),
+                limiter: decision.limiter.to_string(),
+                verdict: decision.verdict,
+                fallback: decision.fallback,
+                retries: decision.retries,
+            });
+        })
+        .build()
+}
+
+fn last(events: &Events) -> Event {
+    events.lock().unwrap().pop().unwrap()
+}
+
+// a backend whose values always change before they're written
+struct Contended;
+
+impl Backend for Contended {
+    fn get(&self, _: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        Err(BackendError::KeyMissing)
+    }
+
+    fn set(&self, _: &str, _: &[u8], _: Option<u64>) -> Result<(), BackendError> {
+        Err(BackendError::ValueChanged)
+    }
+
+    fn delete(&self, _: &str) -> Result<(), BackendError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn decisions() {
+    let events = Events::default();
+    let limiter = limiter(Memory::new(), &events);
+
+    assert!(limiter.is_ratelimited("ip").is_ok());
+    let event = last(&events);
+    assert_eq!(
+        event,
+        Event {
+            key: "ip".to_string(),
+            limiter: "fixed_window".to_string(),
+            verdict: Verdict::Allowed,
+            fallback: None,
+            retries: 0,
+        }
+    );
+
+    assert!(limiter.is_ratelimited("ip").is_err());
+    assert_eq!(last(&events).verdict, Verdict::Limited);
+
+    // reads aren't decisions
+    limiter.get_usage("ip").unwrap();
+    assert!(events.lock().unwrap().is_empty());
+}
+
+#[test]
+fn fallbacks() {
+    let events = Events::default();
+
+    let down = limiter(Down, &events);
+    assert!(down.is_ratelimited("ip").is_ok());
+    let event = last(&events);
+    assert_eq!(event.verdict, Verdict::Allowed);
+    assert_eq!(event.fallback, Some(Fallback::FailOpen));
+    assert_eq!(event.retries, 2);
+
+    let contended = limiter(Contended, &events);
+    assert!(matches!(
+        contended.is_ratelimited("ip"),
+        Err(RateLimiterError::BackendConflict)
+    ));
+    let event = last(&events);
+    assert_eq!(event.verdict, Verdict::Failed);
+    assert_eq!(event.fallback, Some(Fallback::ConflictDeny));
+    assert_eq!(event.retries, 1);
+
+    // a token bucket's instance read by a fixed window
+    let backend = Memory::new();
+    RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(TokenBucket::new(1, Duration::from_secs(60)))
+        .build()
+        .is_ratelimited("ip")
+        .unwrap();
+    assert!(limiter(backend, &events).is_ratelimited("ip").is_ok());
+    let event = last(&events);
+    assert_eq!(event.verdict, Verdict::Allowed);
+    assert_eq!(event.fallback, Some(Fallback::DiscardedInvalidCache));
+}
+
+// a backend retrying on its own, whose single calls always fail
+struct SelfRetrying(Memory);
+
+impl Backend for SelfRetrying {
+    fn get(&self, _: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn set(&self, _: &str, _: &[u8], _: Option<u64>) -> Result<(), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn delete(&self, _: &str) -> Result<(), BackendError> {
+        Err(BackendError::LocalMemLockError)
+    }
+
+    fn get_with_retries(&self, key: &str, _: u32) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        self.0.get(key)
+    }
+
+    fn set_with_retries(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        version: Option<u64>,
+        _: u32,
+    ) -> Result<(), BackendError> {
+        self.0.set(key, &value, version)
+    }
+}
+
+#[test]
+fn backend_retries() {
+    let events = Events::default();
+    let limiter = limiter(SelfRetrying(Memory::new()), &events);
+
+    // decisions observed through listeners use the backend's own retries
+    assert!(limiter.is_ratelimited("ip").is_ok());
+    let event = last(&events);
+    assert_eq!(event.verdict, Verdict::Allowed);
+    assert_eq!(event.fallback, None);
+    assert_eq!(event.retries, 0);
+    assert!(limiter.is_ratelimited("ip").is_err());
+    assert_eq!(last(&events).verdict, Verdict::Limited);
+}
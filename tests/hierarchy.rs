@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use brakes::{
+    backend::{local::Memory, Backend, BackendError},
+    hierarchy::HierarchicalRateLimiter,
+    types::{fixed_window::FixedWindow, token_bucket::TokenBucket, RateLimiterError},
+    RetryStrategy,
+};
+
+// keys look like "<org>/<user>"
+fn org(key: &str) -> Option<String> {
+    key.split_once('/').map(|(org, _)| org.to_string())
+}
+
+#[test]
+fn hierarchical() {
+    let limiter = HierarchicalRateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_level(
+            "user",
+            TokenBucket::new(2, Duration::from_secs(60)),
+            |key| Some(key.to_string()),
+        )
+        .with_level("org", FixedWindow::new(3, Duration::from_secs(60)), org)
+        .with_level(
+            "global",
+            FixedWindow::new(4, Duration::from_secs(60)),
+            |_| Some("global".to_string()),
+        )
+        .build();
+
+    // user limit
+    for i in 0..3 {
+        let result = limiter.is_ratelimited("a/1");
+        assert!(result.is_ok() == (i < 2));
+    }
+    // org limit
+    for i in 0..2 {
+        let result = limiter.is_ratelimited("a/2");
+        assert!(result.is_ok() == (i < 1));
+    }
+    // global limit
+    for i in 0..2 {
+        let result = limiter.is_ratelimited("b/1");
+        assert!(result.is_ok() == (i < 1));
+    }
+    // keys without an org skip that level, but not the global one
+    assert!(limiter.is_ratelimited("anonymous").is_err());
+
+    // denied requests don't consume from any level
+    let usage = limiter.get_usage("a/2").unwrap();
+    let user = usage[0].as_ref().unwrap();
+    assert!(
+        matches!(user, brakes::types::LimiterInstance::TokenBucketInstance(i) if i.tokens() == 1)
+    );
+    let usage = limiter.get_usage("anonymous").unwrap();
+    assert!(usage[0].is_none() && usage[1].is_none());
+    let global = limiter.get_usage("anonymous").unwrap().remove(2).unwrap();
+    assert_eq!(global.as_fixed_window_instance().unwrap().window_count(), 4);
+}
+
+// a memory backend where every write to `key` conflicts with a concurrent one
+struct Contended {
+    memory: Memory,
+    key: &'static str,
+}
+
+impl Backend for Contended {
+    fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        self.memory.get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8], version: Option<u64>) -> Result<(), BackendError> {
+        if key == self.key {
+            return Err(BackendError::ValueChanged);
+        }
+        self.memory.set(key, value, version)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), BackendError> {
+        self.memory.delete(key)
+    }
+}
+
+#[test]
+fn conflict_after_partial_write() {
+    let memory = Memory::new();
+    let limiter = HierarchicalRateLimiter::builder()
+        .with_backend(Contended {
+            memory: memory.clone(),
+            key: "org:a",
+        })
+        .with_level(
+            "user",
+            TokenBucket::new(2, Duration::from_secs(60)),
+            |key| Some(key.to_string()),
+        )
+        .with_level("org", FixedWindow::new(3, Duration::from_secs(60)), org)
+        .with_level(
+            "global",
+            FixedWindow::new(4, Duration::from_secs(60)),
+            |_| Some("global".to_string()),
+        )
+        .with_conflict_strategy(RetryStrategy::RetryAndDeny(1))
+        .build();
+
+    // the user level was written before the org level conflicted: it's rolled back and the request
+    // denied once the conflict strategy gives up
+    assert!(matches!(
+        limiter.is_ratelimited("a/1"),
+        Err(RateLimiterError::BackendConflict)
+    ));
+    let usage = limiter.get_usage("a/1").unwrap();
+    assert!(usage.iter().all(Option::is_none));
+    assert!(memory.get("user:a/1").is_err());
+
+    // a level written before, restored to its previous value
+    let free = HierarchicalRateLimiter::builder()
+        .with_backend(memory.clone())
+        .with_level(
+            "user",
+            TokenBucket::new(2, Duration::from_secs(60)),
+            |key| Some(key.to_string()),
+        )
+        .build();
+    free.is_ratelimited("a/1").unwrap();
+    assert!(limiter.is_ratelimited("a/1").is_err());
+    let usage = limiter.get_usage("a/1").unwrap();
+    assert!(
+        matches!(&usage[0], Some(brakes::types::LimiterInstance::TokenBucketInstance(i)) if i.tokens() == 1)
+    );
+}
@@ -0,0 +1,156 @@
+use brakes::{
+    backend::{local::Memory, Backend},
+    config::RateLimiterConfig,
+    keys::HashTag,
+    types::{
+        concurrency::ConcurrencyLimiter, fixed_window::FixedWindow, token_bucket::TokenBucket,
+    },
+    BuildError, RateLimiter,
+};
+use std::time::Duration;
+
+#[test]
+fn key_pipeline() {
+    let backend = Memory::new();
+    let tenant = String::from("acme");
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(2, Duration::from_secs(60)))
+        .with_key_normalizer(|key| key.to_lowercase())
+        .with_hasher(move |key| format!("{}/{}", tenant, key))
+        .with_key_prefix("fw:")
+        .build();
+
+    assert!(limiter.is_ratelimited("Alice").is_ok());
+    assert!(limiter.is_ratelimited("ALICE").is_ok());
+    assert!(limiter.is_ratelimited("alice").is_err());
+
+    assert_eq!(limiter.backend_key("Alice"), "fw:acme/alice");
+    assert!(backend.get("fw:acme/alice").is_ok());
+    assert!(backend.get("Alice").is_err());
+
+    // reads go through the same pipeline as writes
+    assert_eq!(
+        limiter
+            .get_usage("Alice")
+            .unwrap()
+            .as_fixed_window_instance()
+            .unwrap()
+            .window_count(),
+        2
+    );
+}
+
+#[test]
+fn hashed_leases() {
+    let backend = Memory::new();
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(ConcurrencyLimiter::new(1, Duration::from_secs(60)))
+        .with_hasher(|key| key.chars().rev().collect())
+        .build();
+
+    let lease = limiter.acquire("abc").unwrap();
+    assert!(backend.get("cba").is_ok());
+    assert!(limiter.acquire("abc").is_err());
+    // released under the hashed key
+    drop(lease);
+    assert!(limiter.acquire("abc").is_ok());
+}
+
+#[test]
+fn max_key_length() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_key_prefix("user-agent:")
+        .with_max_key_length(64)
+        .build();
+
+    let agent = "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko)";
+    let key = limiter.backend_key(agent);
+    assert_eq!(key.len(), 64);
+    assert!(key.starts_with("user-agent:Mozilla/5.0"));
+    assert_ne!(key, limiter.backend_key(&format!("{} Chrome", agent)));
+
+    assert!(limiter.is_ratelimited(agent).is_ok());
+    assert!(limiter.is_ratelimited(agent).is_err());
+}
+
+#[cfg(feature = "sha256")]
+#[test]
+fn sha256() {
+    use brakes::keys;
+
+    assert_eq!(
+        keys::sha256("abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_hasher(keys::sha256)
+        .build();
+    assert_eq!(limiter.backend_key("abc"), keys::sha256("abc"));
+}
+
+#[cfg(feature = "xxhash")]
+#[test]
+fn xxh3() {
+    use brakes::keys;
+
+    assert_eq!(keys::xxh3("").len(), 16);
+    assert_eq!(keys::xxh3(""), "2d06800538d394c2");
+}
+
+#[test]
+fn namespaces() {
+    let backend = Memory::new();
+    let fixed_window = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_namespace("fw")
+        .with_discard_invalid_cache_entries(false)
+        .build();
+    let token_bucket = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(TokenBucket::new(1, Duration::from_secs(60)))
+        .with_namespace("tb")
+        .with_hash_tag(HashTag::Key)
+        .with_discard_invalid_cache_entries(false)
+        .build();
+
+    // the same client doesn't collide across limiters
+    assert!(fixed_window.is_ratelimited("10.0.0.1").is_ok());
+    assert!(token_bucket.is_ratelimited("10.0.0.1").is_ok());
+    assert!(backend.get("fw:10.0.0.1").is_ok());
+    assert!(backend.get("tb:{10.0.0.1}").is_ok());
+    assert!(fixed_window.is_ratelimited("10.0.0.1").is_err());
+    assert!(token_bucket.is_ratelimited("10.0.0.1").is_err());
+
+    let result = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_namespace("{fw}")
+        .try_build();
+    assert_eq!(
+        result.unwrap_err(),
+        BuildError::InvalidNamespace("{fw}".to_string())
+    );
+}
+
+#[test]
+fn namespace_from_config() {
+    let config: RateLimiterConfig = toml::from_str(
+        r#"
+        namespace = "login"
+        hash_tag = "key"
+
+        [limiter]
+        type = "fixed_window"
+        rate = "5/min"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.build().unwrap().backend_key("ip"), "login:{ip}");
+}
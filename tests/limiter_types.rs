@@ -1,10 +1,20 @@
-use std::{thread::sleep, time::Duration};
+use std::{
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use brakes::{
     backend::local::Memory,
     types::{
-        fixed_window::FixedWindow, leaky_bucket::LeakyBucket, sliding_window::SlidingWindowCounter,
+        adaptive::Adaptive,
+        calendar::{CalendarWindow, Period},
+        concurrency::ConcurrencyLimiter,
+        fixed_window::FixedWindow,
+        leaky_bucket::LeakyBucket,
+        sliding_window::SlidingWindowCounter,
+        sliding_window_log::SlidingWindowLog,
         token_bucket::TokenBucket,
+        LimiterType, Outcome,
     },
     RateLimiter,
 };
@@ -32,6 +42,25 @@ fn fixed_window() {
     assert_eq!(usage.window_count(), 2);
 }
 
+#[test]
+fn calendar_window() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(CalendarWindow::new(2, Period::Day).with_utc_offset(-5 * 3600))
+        .build();
+    for i in 0..3 {
+        let result = limiter.is_ratelimited("ip");
+        assert!(result.is_ok() == (i < 2))
+    }
+    let usage = limiter
+        .get_usage("ip")
+        .unwrap()
+        .as_fixed_window_instance()
+        .unwrap();
+    // midnight at UTC-5
+    assert_eq!(usage.window_start() % (24 * 3600 * 1000), 5 * 3600 * 1000);
+}
+
 #[test]
 fn sliding_window_counter() {
     let limiter = RateLimiter::builder()
@@ -47,6 +76,32 @@ fn sliding_window_counter() {
     }
 }
 
+#[test]
+fn sliding_window_log() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(SlidingWindowLog::new(3, Duration::from_millis(200)))
+        .build();
+    for i in 0..4 {
+        let result = limiter.is_ratelimited("ip");
+        assert!(result.is_ok() == (i < 3))
+    }
+
+    // the whole log leaves the window
+    sleep(Duration::from_millis(200));
+
+    for i in 0..4 {
+        let result = limiter.is_ratelimited("ip");
+        assert!(result.is_ok() == (i < 3))
+    }
+    let usage = limiter
+        .get_usage("ip")
+        .unwrap()
+        .as_sliding_window_log_instance()
+        .unwrap();
+    assert_eq!(usage.count(), 3);
+}
+
 #[test]
 fn token_bucket() {
     let limiter = RateLimiter::builder()
@@ -86,3 +141,119 @@ fn leaky_bucket() {
         assert!(result.is_ok() == (i < 2))
     }
 }
+
+#[test]
+fn leaky_bucket_shaping() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(
+            LeakyBucket::new(1, Duration::from_millis(50))
+                .with_max_queue_wait(Duration::from_millis(100)),
+        )
+        .build();
+
+    assert_eq!(limiter.reserve("ip").unwrap(), Duration::ZERO);
+    let delay = limiter.reserve("ip").unwrap();
+    assert!(delay > Duration::from_millis(40) && delay <= Duration::from_millis(50));
+    let delay = limiter.reserve("ip").unwrap();
+    assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+    assert!(limiter.reserve("ip").is_err());
+
+    // wait() sleeps until the request's turn comes
+    sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    limiter.wait("ip").unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
+
+#[test]
+fn concurrency() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(ConcurrencyLimiter::new(2, Duration::from_millis(200)))
+        .build();
+
+    let first = limiter.acquire("ip").unwrap();
+    let second = limiter.acquire("ip").unwrap();
+    assert!(first.id().is_some());
+    assert!(limiter.acquire("ip").is_err());
+
+    // dropping a lease frees its slot
+    drop(first);
+    let third = limiter.acquire("ip").unwrap();
+    assert!(limiter.acquire("ip").is_err());
+    third.release().unwrap();
+    let usage = limiter
+        .get_usage("ip")
+        .unwrap()
+        .as_concurrency_instance()
+        .unwrap();
+    assert_eq!(usage.in_flight(), 1);
+
+    // leases that are never released expire after their ttl
+    std::mem::forget(second);
+    std::mem::forget(limiter.acquire("ip").unwrap());
+    assert!(limiter.acquire("ip").is_err());
+    sleep(Duration::from_millis(200));
+    for i in 0..3 {
+        let result = limiter.acquire("ip");
+        assert!(result.is_ok() == (i < 2));
+        std::mem::forget(result);
+    }
+}
+
+#[test]
+fn adaptive() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(Adaptive::new(1, 4, Duration::from_millis(100)).with_initial_limit(2))
+        .build();
+    for i in 0..3 {
+        let result = limiter.is_ratelimited("ip");
+        assert!(result.is_ok() == (i < 2))
+    }
+
+    // two successes (the current limit) raise the limit to 3
+    limiter.report("ip", Outcome::Success).unwrap();
+    limiter.report("ip", Outcome::Success).unwrap();
+    assert!(limiter.is_ratelimited("ip").is_ok());
+    assert!(limiter.is_ratelimited("ip").is_err());
+
+    // a failure drops it to 1
+    limiter.report("ip", Outcome::Failure).unwrap();
+    sleep(Duration::from_millis(100));
+    for i in 0..2 {
+        let result = limiter.is_ratelimited("ip");
+        assert!(result.is_ok() == (i < 1))
+    }
+    let usage = limiter
+        .get_usage("ip")
+        .unwrap()
+        .as_adaptive_instance()
+        .unwrap();
+    assert_eq!(usage.limit(), 1);
+
+    // feedback for keys that weren't limited yet is ignored
+    limiter.report("other", Outcome::Failure).unwrap();
+    assert!(limiter.get_usage("other").is_err());
+}
+
+#[test]
+fn remaining() {
+    fn after_one<T: LimiterType + Clone>(limiter: T) -> Option<u32> {
+        let rate_limiter = RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(limiter.clone())
+            .build();
+        rate_limiter.is_ratelimited("ip").unwrap();
+        limiter.remaining(&rate_limiter.get_usage("ip").unwrap())
+    }
+
+    let minute = Duration::from_secs(60);
+    assert_eq!(after_one(FixedWindow::new(3, minute)), Some(2));
+    assert_eq!(after_one(SlidingWindowLog::new(3, minute)), Some(2));
+    assert_eq!(after_one(TokenBucket::new(3, minute)), Some(2));
+    assert_eq!(after_one(LeakyBucket::new(3, minute)), Some(2));
+    assert_eq!(after_one(ConcurrencyLimiter::new(3, minute)), Some(2));
+    assert_eq!(after_one(SlidingWindowCounter::new(3, minute)), Some(2));
+}
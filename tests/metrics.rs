@@ -0,0 +1,110 @@
+#![cfg(feature = "metrics")]
+
+mod common;
+
+use brakes::{
+    backend::{local::Memory, Backend, BackendError},
+    metrics::PrometheusMetrics,
+    types::fixed_window::FixedWindow,
+    RateLimiter, RetryStrategy,
+};
+use common::Down;
+use std::time::Duration;
+
+// a backend where every write conflicts with a concurrent one
+struct Contended(Memory);
+
+impl Backend for Contended {
+    fn get(&self, key: &str) -> Result<(Vec<u8>, Option<u64>), BackendError> {
+        self.0.get(key)
+    }
+
+    fn set(&self, _: &str, _: &[u8], _: Option<u64>) -> Result<(), BackendError> {
+        Err(BackendError::ValueChanged)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), BackendError> {
+        self.0.delete(key)
+    }
+
+    fn name(&self) -> &str {
+        "contended"
+    }
+}
+
+#[test]
+fn prometheus() {
+    let metrics = PrometheusMetrics::new();
+    let login = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .on_decision(metrics.listener("login"))
+        .build();
+    let search = RateLimiter::builder()
+        .with_backend(Down)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_failure_strategy(RetryStrategy::RetryAndAllow(2))
+        .on_decision(metrics.listener("search"))
+        .build();
+    let signup = RateLimiter::builder()
+        .with_backend(Contended(Memory::new()))
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_conflict_strategy(RetryStrategy::RetryAndDeny(2))
+        .on_decision(metrics.listener("signup"))
+        .build();
+
+    assert!(login.is_ratelimited("ip").is_ok());
+    assert!(login.is_ratelimited("ip").is_err());
+    assert!(search.is_ratelimited("ip").is_ok());
+    assert!(signup.is_ratelimited("ip").is_err());
+
+    let text = metrics.render().unwrap();
+    for line in [
+        r#"brakes_decisions_total{backend="memory",limiter="login",verdict="allowed"} 1"#,
+        r#"brakes_decisions_total{backend="memory",limiter="login",verdict="limited"} 1"#,
+        r#"brakes_decisions_total{backend="down",limiter="search",verdict="allowed"} 1"#,
+        r#"brakes_fallbacks_total{backend="down",fallback="fail_open",limiter="search"} 1"#,
+        r#"brakes_retries_total{backend="down",limiter="search"} 2"#,
+        r#"brakes_fallbacks_total{backend="contended",fallback="conflict_deny",limiter="signup"} 1"#,
+        r#"brakes_conflicts_total{backend="contended",limiter="signup"} 3"#,
+        r#"brakes_retries_total{backend="contended",limiter="signup"} 2"#,
+        r#"brakes_decision_duration_seconds_count{backend="memory",limiter="login"} 2"#,
+        r#"brakes_backend_duration_seconds_count{backend="down",limiter="search"} 1"#,
+    ] {
+        assert!(text.contains(line), "{} missing from\n{}", line, text);
+    }
+    assert!(!text.contains(r#"fallback="fail_open",limiter="login""#));
+    assert!(!text.contains(r#"brakes_conflicts_total{backend="down""#));
+}
+
+#[test]
+fn shared_registry() {
+    let registry = prometheus::Registry::new();
+
+    // a conflict on the third metric leaves the registry as it was, the call succeeds once it's gone
+    let retries = prometheus::IntCounterVec::new(
+        prometheus::Opts::new(
+            "brakes_retries_total",
+            "Backend calls tried again after a failure or a conflict.",
+        ),
+        &["limiter", "backend"],
+    )
+    .unwrap();
+    registry.register(Box::new(retries.clone())).unwrap();
+    assert!(PrometheusMetrics::with_registry(&registry).is_err());
+    registry.unregister(Box::new(retries)).unwrap();
+
+    let metrics = PrometheusMetrics::with_registry(&registry).unwrap();
+    assert!(PrometheusMetrics::with_registry(&registry).is_err());
+
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .on_decision(metrics.listener("login"))
+        .build();
+    limiter.is_ratelimited("ip").unwrap();
+    assert!(registry
+        .gather()
+        .iter()
+        .any(|family| family.get_name() == "brakes_decisions_total"));
+}
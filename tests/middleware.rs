@@ -0,0 +1,73 @@
+#![cfg(feature = "tower")]
+
+use brakes::{
+    backend::local::Memory,
+    middleware::{tower::TowerRateLimiterLayer, Unregistered},
+    registry::LimiterRegistry,
+    types::fixed_window::FixedWindow,
+    RateLimiter,
+};
+use futures::executor::block_on;
+use http::{Request, Response, StatusCode};
+use std::{
+    convert::Infallible,
+    future::{ready, Ready},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower::{Layer, Service};
+
+#[derive(Clone)]
+struct Ok200;
+
+impl Service<Request<()>> for Ok200 {
+    type Response = Response<String>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Request<()>) -> Self::Future {
+        ready(Ok(Response::new(String::new())))
+    }
+}
+
+fn status<S>(service: &mut S) -> StatusCode
+where
+    S: Service<Request<()>, Response = Response<String>, Error = Infallible>,
+{
+    block_on(service.call(Request::new(()))).unwrap().status()
+}
+
+#[test]
+fn unregistered() {
+    let registry = LimiterRegistry::new();
+    let layer = |unregistered| {
+        TowerRateLimiterLayer::from_registry(
+            &registry,
+            "login",
+            brakes::middleware::tower::default_callback,
+            |_: &Request<()>| "ip".to_string(),
+        )
+        .with_unregistered(unregistered)
+        .layer(Ok200)
+    };
+    let mut allowing = layer(Unregistered::Allow);
+    let mut denying = layer(Unregistered::Deny);
+
+    assert_eq!(status(&mut allowing), StatusCode::OK);
+    assert_eq!(status(&mut denying), StatusCode::TOO_MANY_REQUESTS);
+
+    registry.insert(
+        "login",
+        RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+            .build()
+            .into_dyn(),
+    );
+    assert_eq!(status(&mut denying), StatusCode::OK);
+    assert_eq!(status(&mut allowing), StatusCode::TOO_MANY_REQUESTS);
+}
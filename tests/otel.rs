@@ -0,0 +1,170 @@
+#![cfg(all(feature = "otel", feature = "tower"))]
+
+use brakes::{
+    backend::local::Memory, middleware::tower::TowerRateLimiterLayer, registry::LimiterRegistry,
+    types::fixed_window::FixedWindow, RateLimiter,
+};
+use futures::executor::block_on;
+use http::{Request, Response, StatusCode};
+use opentelemetry::{
+    trace::{Span, SpanContext, Status, TraceContextExt},
+    Context, KeyValue, Value,
+};
+use std::{
+    borrow::Cow,
+    convert::Infallible,
+    future::{ready, Ready},
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll},
+    time::{Duration, SystemTime},
+};
+use tower::{Layer, Service};
+
+// a span keeping the attributes set on it
+#[derive(Clone, Default)]
+struct Recorded(Arc<Mutex<Vec<KeyValue>>>);
+
+impl Recorded {
+    fn get(&self, key: &str) -> Option<Value> {
+        let attributes = self.0.lock().unwrap();
+        let attribute = attributes.iter().rev().find(|kv| kv.key.as_str() == key)?;
+        Some(attribute.value.clone())
+    }
+}
+
+struct RecordingSpan(Recorded, SpanContext);
+
+impl Span for RecordingSpan {
+    fn add_event_with_timestamp<T>(&mut self, _: T, _: SystemTime, _: Vec<KeyValue>)
+    where
+        T: Into<Cow<'static, str>>,
+    {
+    }
+
+    fn span_context(&self) -> &SpanContext {
+        &self.1
+    }
+
+    fn is_recording(&self) -> bool {
+        true
+    }
+
+    fn set_attribute(&mut self, attribute: KeyValue) {
+        self.0 .0.lock().unwrap().push(attribute);
+    }
+
+    fn set_status(&mut self, _: Status) {}
+
+    fn update_name<T>(&mut self, _: T)
+    where
+        T: Into<Cow<'static, str>>,
+    {
+    }
+
+    fn add_link(&mut self, _: SpanContext, _: Vec<KeyValue>) {}
+
+    fn end_with_timestamp(&mut self, _: SystemTime) {}
+}
+
+#[derive(Clone)]
+struct Ok200;
+
+impl Service<Request<()>> for Ok200 {
+    type Response = Response<String>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Request<()>) -> Self::Future {
+        ready(Ok(Response::new(String::new())))
+    }
+}
+
+// sends a request within a span of its own, returning the status and the span's attributes
+fn send<S>(service: &mut S) -> (StatusCode, Recorded)
+where
+    S: Service<Request<()>, Response = Response<String>, Error = Infallible>,
+{
+    let recorded = Recorded::default();
+    let span = RecordingSpan(recorded.clone(), SpanContext::empty_context());
+    let _guard = Context::current_with_span(span).attach();
+    let response = block_on(service.call(Request::new(()))).unwrap();
+    (response.status(), recorded)
+}
+
+#[test]
+fn span_attributes() {
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(2, Duration::from_secs(60)))
+        .build();
+    let mut service =
+        TowerRateLimiterLayer::default(limiter, |_: &Request<()>| "ip".to_string()).layer(Ok200);
+
+    let (status, span) = send(&mut service);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(span.get("ratelimit.limiter"), Some("fixed_window".into()));
+    assert_eq!(span.get("ratelimit.decision"), Some("allowed".into()));
+    assert_eq!(span.get("ratelimit.remaining"), Some(1.into()));
+
+    let (_, span) = send(&mut service);
+    assert_eq!(span.get("ratelimit.remaining"), Some(0.into()));
+
+    let (status, span) = send(&mut service);
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(span.get("ratelimit.decision"), Some("limited".into()));
+    assert_eq!(span.get("ratelimit.remaining"), Some(0.into()));
+}
+
+#[test]
+fn registry_names() {
+    let registry = LimiterRegistry::new();
+    let mut service = TowerRateLimiterLayer::from_registry(
+        &registry,
+        "login",
+        brakes::middleware::tower::default_callback,
+        |_: &Request<()>| "ip".to_string(),
+    )
+    .layer(Ok200);
+
+    // nothing registered yet, nothing recorded
+    let (status, span) = send(&mut service);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(span.get("ratelimit.decision"), None);
+
+    registry.insert(
+        "login",
+        RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(FixedWindow::new(2, Duration::from_secs(60)))
+            .build()
+            .into_dyn(),
+    );
+    let (_, span) = send(&mut service);
+    assert_eq!(span.get("ratelimit.limiter"), Some("login".into()));
+    assert_eq!(span.get("ratelimit.decision"), Some("allowed".into()));
+}
+
+#[test]
+fn custom_names() {
+    let limiter = || {
+        RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(FixedWindow::new(2, Duration::from_secs(60)))
+            .build()
+    };
+    let mut login = TowerRateLimiterLayer::default(limiter(), |_: &Request<()>| "ip".to_string())
+        .with_name("login")
+        .layer(Ok200);
+    let mut search = TowerRateLimiterLayer::default(limiter(), |_: &Request<()>| "ip".to_string())
+        .layer(Ok200)
+        .with_name("search");
+
+    let (_, span) = send(&mut login);
+    assert_eq!(span.get("ratelimit.limiter"), Some("login".into()));
+    let (_, span) = send(&mut search);
+    assert_eq!(span.get("ratelimit.limiter"), Some("search".into()));
+}
@@ -0,0 +1,106 @@
+use brakes::{
+    backend::local::Memory, config::RateLimiterConfig, registry::LimiterRegistry,
+    types::fixed_window::FixedWindow, RateLimiter,
+};
+use std::{collections::HashMap, time::Duration};
+
+fn configs(toml: &str) -> HashMap<String, RateLimiterConfig> {
+    toml::from_str(toml).unwrap()
+}
+
+#[test]
+fn registry() {
+    let registry = LimiterRegistry::new();
+    assert!(registry.get("login").is_none());
+
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .build();
+    assert!(registry.insert("login", limiter.into_dyn()).is_none());
+
+    // clones share the same limiters
+    let login = registry.clone().get("login").unwrap();
+    assert!(login.is_ratelimited("ip").is_ok());
+    assert!(login.is_ratelimited("ip").is_err());
+
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(5, Duration::from_secs(60)))
+        .build();
+    assert!(registry.insert("login", limiter.into_dyn()).is_some());
+    assert!(registry.get("login").unwrap().is_ratelimited("ip").is_ok());
+
+    assert!(registry.remove("login").is_some());
+    assert!(registry.names().is_empty());
+}
+
+#[test]
+fn reload() {
+    let registry = LimiterRegistry::new();
+    let backend = Memory::new();
+    registry
+        .reload_with_backend(
+            &configs(
+                r#"
+                [login.limiter]
+                type = "fixed_window"
+                rate = "2/min"
+
+                [api.limiter]
+                type = "token_bucket"
+                rate = "100/min"
+                "#,
+            ),
+            backend.clone(),
+        )
+        .unwrap();
+    let mut names = registry.names();
+    names.sort();
+    assert_eq!(names, ["api", "login"]);
+
+    let login = registry.get("login").unwrap();
+    assert!(login.is_ratelimited("login:ip").is_ok());
+    assert!(login.is_ratelimited("login:ip").is_ok());
+    assert!(login.is_ratelimited("login:ip").is_err());
+
+    // a raised threshold applies to the existing counters
+    registry
+        .reload_with_backend(
+            &configs(
+                r#"
+                [login.limiter]
+                type = "fixed_window"
+                rate = "3/min"
+                "#,
+            ),
+            backend.clone(),
+        )
+        .unwrap();
+    assert!(registry.get("api").is_none());
+    let login = registry.get("login").unwrap();
+    assert!(login.is_ratelimited("login:ip").is_ok());
+    assert!(login.is_ratelimited("login:ip").is_err());
+
+    // nothing changes if one of the limiters can't be built
+    let invalid = configs(
+        r#"
+        [login.limiter]
+        type = "fixed_window"
+        rate = "10/min"
+
+        [daily.limiter]
+        type = "calendar_window"
+        limit = 10
+        period = "day"
+        timezone = "Mars/Olympus_Mons"
+        "#,
+    );
+    assert!(registry.reload(&invalid).is_err());
+    assert_eq!(registry.names(), ["login"]);
+    assert!(registry
+        .get("login")
+        .unwrap()
+        .is_ratelimited("login:ip")
+        .is_err());
+}
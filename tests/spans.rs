@@ -0,0 +1,165 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use brakes::{
+    backend::{local::Memory, BackendError},
+    events::TracedKey,
+    types::{fixed_window::FixedWindow, token_bucket::TokenBucket},
+    RateLimiter, RetryStrategy,
+};
+use common::Down;
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Metadata, Subscriber,
+};
+
+// records spans and events as lines of text, ex: "rate_limit key=ip limiter=fixed_window"
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0 += &format!(" {}={:?}", field.name(), value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0 += &format!(" {}={}", field.name(), value);
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut fields = Fields(span.metadata().name().to_string());
+        span.record(&mut fields);
+        let mut lines = self.0.lock().unwrap();
+        lines.push(fields.0);
+        span::Id::from_u64(lines.len() as u64)
+    }
+
+    // appended to the span's line
+    fn record(&self, span: &span::Id, values: &span::Record<'_>) {
+        let mut fields = Fields(String::new());
+        values.record(&mut fields);
+        self.0.lock().unwrap()[span.into_u64() as usize - 1] += &fields.0;
+    }
+
+    fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields(event.metadata().level().to_string());
+        event.record(&mut fields);
+        self.0.lock().unwrap().push(fields.0);
+    }
+
+    fn enter(&self, _: &span::Id) {}
+
+    fn exit(&self, _: &span::Id) {}
+}
+
+impl Recorder {
+    fn take(&self) -> Vec<String> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+#[test]
+fn spans() {
+    let recorder = Recorder::default();
+    let limiter = RateLimiter::builder()
+        .with_backend(Memory::new())
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .build();
+
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert!(limiter.is_ratelimited("ip").is_ok());
+        assert!(limiter.is_ratelimited("ip").is_err());
+    });
+    assert_eq!(
+        recorder.take(),
+        [
+            "rate_limit key=ip limiter=fixed_window backend=memory verdict=allowed retries=0",
+            "rate_limit key=ip limiter=fixed_window backend=memory verdict=limited retries=0",
+        ]
+    );
+}
+
+#[test]
+fn redacted_keys() {
+    let recorder = Recorder::default();
+    let builder = || {
+        RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+            .with_hasher(|key| key.chars().rev().collect())
+    };
+    // the backend key by default
+    let backend_key = builder().build();
+    let plain = builder().with_traced_key(TracedKey::Plain).build();
+    let redacted = builder().with_traced_key(TracedKey::Redacted).build();
+
+    tracing::subscriber::with_default(recorder.clone(), || {
+        backend_key.is_ratelimited("abc").unwrap();
+        plain.is_ratelimited("abc").unwrap();
+        redacted.is_ratelimited("abc").unwrap();
+    });
+    let lines = recorder.take();
+    assert!(lines[0].starts_with("rate_limit key=cba limiter=fixed_window"));
+    assert!(lines[1].starts_with("rate_limit key=abc limiter=fixed_window"));
+    assert!(lines[2].starts_with("rate_limit limiter=fixed_window"));
+}
+
+#[test]
+fn fallback_events() {
+    let recorder = Recorder::default();
+    let down = RateLimiter::builder()
+        .with_backend(Down)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .with_failure_strategy(RetryStrategy::RetryAndAllow(2))
+        .build();
+
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert!(down.is_ratelimited("ip").is_ok());
+    });
+    let error = BackendError::LocalMemLockError.to_string();
+    assert_eq!(
+        recorder.take(),
+        [
+            "rate_limit key=ip limiter=fixed_window backend=down verdict=allowed fallback=fail_open \
+             retries=2"
+                .to_string(),
+            format!("DEBUG message=backend call failed error={} tries=3", error),
+            format!("WARN message=backend failed, request allowed error={}", error),
+        ]
+    );
+
+    // a token bucket's instance read by a fixed window
+    let backend = Memory::new();
+    RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(TokenBucket::new(1, Duration::from_secs(60)))
+        .build()
+        .is_ratelimited("ip")
+        .unwrap();
+    let limiter = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(FixedWindow::new(1, Duration::from_secs(60)))
+        .build();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        assert!(limiter.is_ratelimited("ip").is_ok());
+    });
+    assert!(recorder
+        .take()
+        .contains(&"WARN message=discarded a cache entry of another limiter type".to_string()));
+}
@@ -0,0 +1,310 @@
+use brakes::{
+    backend::{local::Memory, Backend},
+    storage::{self, Codec, ConfigChangePolicy, Envelope},
+    types::{
+        fixed_window::FixedWindow,
+        token_bucket::{TokenBucket, TokenBucketInstance, TOKEN_SCALE},
+        LimiterInstance, LimiterType, RateLimiterError,
+    },
+    RateLimiter,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[test]
+fn envelope() {
+    let backend = Memory::new();
+    let bucket = TokenBucket::new(10, Duration::from_secs(60));
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(bucket.clone())
+        .build();
+
+    assert!(limiter.is_ratelimited("key").is_ok());
+
+    let (value, _) = backend.get("key").unwrap();
+    assert!(value.starts_with(b"bk"));
+    let envelope: Envelope = bincode::deserialize(&value[2..]).unwrap();
+    assert_eq!(envelope.version(), storage::FORMAT_VERSION);
+    assert_eq!(envelope.limiter(), "token_bucket");
+    assert_eq!(envelope.fingerprint(), bucket.fingerprint());
+    let usage = limiter
+        .get_usage("key")
+        .unwrap()
+        .as_token_bucket_instance()
+        .unwrap();
+    assert_eq!(usage.tokens(), 9);
+}
+
+#[test]
+fn legacy_value() {
+    // written before envelopes, `FixedWindow::new(5, 100 years)` after 5 requests
+    let full = [
+        0, 0, 0, 0, 84, 32, 20, 81, 161, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0,
+    ];
+    let backend = Memory::new();
+    backend.set("key", &full, None).unwrap();
+
+    let limiter = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(FixedWindow::new(5, Duration::from_secs(100 * 365 * 86400)))
+        .build();
+    assert!(limiter.is_ratelimited("key").is_err());
+}
+
+#[test]
+fn legacy_token_bucket() {
+    // written by a release counting tokens as an f32, `TokenBucket::new(10, 100 years)` emptied by
+    // 10 requests, and after 7 of them
+    let empty = [
+        2, 0, 0, 0, 0, 0, 0, 0, 191, 235, 19, 81, 161, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let three_left = [
+        2, 0, 0, 0, 0, 0, 64, 64, 191, 235, 19, 81, 161, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+    let backend = Memory::new();
+    backend.set("empty", &empty, None).unwrap();
+    backend.set("three_left", &three_left, None).unwrap();
+
+    let limiter = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(TokenBucket::new(10, Duration::from_secs(100 * 365 * 86400)))
+        .build();
+    let usage = limiter
+        .get_usage("three_left")
+        .unwrap()
+        .as_token_bucket_instance()
+        .unwrap();
+    assert_eq!(usage.scaled_tokens(), 3 * TOKEN_SCALE);
+
+    assert!(limiter.is_ratelimited("empty").is_err());
+    for i in 0..4 {
+        assert!(limiter.is_ratelimited("three_left").is_ok() == (i < 3));
+    }
+}
+
+#[test]
+fn unknown_version() {
+    let backend = Memory::new();
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(10, Duration::from_secs(60)))
+        .with_discard_invalid_cache_entries(false)
+        .build();
+    assert!(limiter.is_ratelimited("key").is_ok());
+
+    // the same envelope, tagged with a version from the future
+    let (mut value, _) = backend.get("key").unwrap();
+    value[2..4].copy_from_slice(&(storage::FORMAT_VERSION + 1).to_le_bytes());
+    backend.set("key", &value, None).unwrap();
+    assert!(matches!(
+        limiter.is_ratelimited("key"),
+        Err(RateLimiterError::MalformedValue(_))
+    ));
+
+    #[cfg(feature = "json")]
+    {
+        let limiter = RateLimiter::builder()
+            .with_backend(backend.clone())
+            .with_limiter(FixedWindow::new(10, Duration::from_secs(60)))
+            .with_codec(Codec::Json)
+            .with_discard_invalid_cache_entries(false)
+            .build();
+        assert!(limiter.is_ratelimited("json").is_ok());
+        let (value, _) = backend.get("json").unwrap();
+        let mut json: serde_json::Value = serde_json::from_slice(&value).unwrap();
+        json["version"] = (storage::FORMAT_VERSION + 1).into();
+        backend
+            .set("json", &serde_json::to_vec(&json).unwrap(), None)
+            .unwrap();
+        assert!(limiter.is_ratelimited("json").is_err());
+    }
+}
+
+#[test]
+fn version_1() {
+    let backend = Memory::new();
+    let bucket = TokenBucket::new(10, Duration::from_secs(60));
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(bucket.clone())
+        .with_discard_invalid_cache_entries(false)
+        .build();
+
+    // written before envelopes had a limit, a bucket with a token left
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let instance = LimiterInstance::TokenBucketInstance(TokenBucketInstance::new(now, 1));
+    let mut value = b"bk".to_vec();
+    bincode::serialize_into(
+        &mut value,
+        &(1u16, "token_bucket", bucket.fingerprint(), instance),
+    )
+    .unwrap();
+    backend.set("key", &value, None).unwrap();
+
+    assert!(limiter.is_ratelimited("key").is_ok());
+    // rewritten with the current version
+    let (value, _) = backend.get("key").unwrap();
+    let envelope: Envelope = bincode::deserialize(&value[2..]).unwrap();
+    assert_eq!(envelope.version(), 2);
+    assert!(matches!(
+        limiter.is_ratelimited("key"),
+        Err(RateLimiterError::RateExceeded)
+    ));
+}
+
+#[test]
+fn migration() {
+    let backend = Memory::new();
+    let fixed_window = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(10, Duration::from_secs(60)))
+        .build();
+    for _ in 0..8 {
+        fixed_window.is_ratelimited("migrated").unwrap();
+        fixed_window.is_ratelimited("discarded").unwrap();
+    }
+
+    let bucket = TokenBucket::new(10, Duration::from_secs(60));
+    let migrating = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(bucket.clone())
+        .with_migration("fixed_window", storage::fixed_window_to_token_bucket(10))
+        .build();
+    let discarding = RateLimiter::builder()
+        .with_backend(backend)
+        .with_limiter(bucket)
+        .build();
+
+    // the 8 requests already counted are taken out of the bucket
+    for i in 0..3 {
+        assert!(migrating.is_ratelimited("migrated").is_ok() == (i < 2));
+    }
+
+    // without a migration the entry is discarded and the quota starts over
+    for _ in 0..10 {
+        assert!(discarding.is_ratelimited("discarded").is_ok());
+    }
+}
+
+#[test]
+fn config_change() {
+    let backend = Memory::new();
+    let before = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(10, Duration::from_secs(60)))
+        .build();
+    for key in ["keep", "rescale", "reset"] {
+        for _ in 0..6 {
+            before.is_ratelimited(key).unwrap();
+        }
+    }
+
+    let after = |policy| {
+        RateLimiter::builder()
+            .with_backend(backend.clone())
+            .with_limiter(FixedWindow::new(5, Duration::from_secs(60)))
+            .with_config_change_policy(policy)
+            .build()
+    };
+
+    // 6 requests counted against the new threshold of 5
+    assert!(after(ConfigChangePolicy::Keep)
+        .is_ratelimited("keep")
+        .is_err());
+
+    // 6 out of 10 become 3 out of 5
+    let rescale = after(ConfigChangePolicy::Rescale);
+    for i in 0..3 {
+        assert!(rescale.is_ratelimited("rescale").is_ok() == (i < 2));
+    }
+
+    let reset = after(ConfigChangePolicy::Reset);
+    for i in 0..6 {
+        assert!(reset.is_ratelimited("reset").is_ok() == (i < 5));
+    }
+}
+
+#[test]
+fn codecs() {
+    let codecs = [
+        Codec::Bincode,
+        Codec::Compact,
+        #[cfg(feature = "json")]
+        Codec::Json,
+        #[cfg(feature = "msgpack")]
+        Codec::MessagePack,
+    ];
+
+    for codec in codecs {
+        let limiter = RateLimiter::builder()
+            .with_backend(Memory::new())
+            .with_limiter(FixedWindow::new(2, Duration::from_secs(60)))
+            .with_codec(codec)
+            .with_discard_invalid_cache_entries(false)
+            .build();
+
+        for i in 0..3 {
+            assert!(limiter.is_ratelimited("key").is_ok() == (i < 2));
+        }
+        let usage = limiter
+            .get_usage("key")
+            .unwrap()
+            .as_fixed_window_instance()
+            .unwrap();
+        assert_eq!(usage.window_count(), 2);
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_schema() {
+    use brakes::types::concurrency::ConcurrencyLimiter;
+
+    let backend = Memory::new();
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(FixedWindow::new(10, Duration::from_secs(60)))
+        .with_codec(Codec::Json)
+        .build();
+    limiter.is_ratelimited("key").unwrap();
+
+    let (value, _) = backend.get("key").unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&value).unwrap();
+    assert_eq!(json["version"], storage::FORMAT_VERSION);
+    assert_eq!(json["limiter"], "fixed_window");
+    assert_eq!(json["limit"], 10);
+    assert_eq!(json["instance"]["FixedWindowInstance"]["count"], 1);
+    // 64 and 128-bit integers are strings
+    let fingerprint = FixedWindow::new(10, Duration::from_secs(60)).fingerprint();
+    assert_eq!(json["fingerprint"], fingerprint.to_string());
+    assert!(json["instance"]["FixedWindowInstance"]["window_start"].is_string());
+
+    // written by another service
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let value = format!(
+        r#"{{"version":1,"limiter":"fixed_window","fingerprint":{},"limit":10,"instance":{{"FixedWindowInstance":{{"window_start":{},"count":10}}}}}}"#,
+        fingerprint, now
+    );
+    backend.set("key", value.as_bytes(), None).unwrap();
+    assert!(limiter.is_ratelimited("key").is_err());
+
+    // lease ids are random, most don't fit a double
+    let limiter = RateLimiter::builder()
+        .with_backend(backend.clone())
+        .with_limiter(ConcurrencyLimiter::new(2, Duration::from_secs(60)))
+        .with_codec(Codec::Json)
+        .build();
+    let _lease = limiter.acquire("leases").unwrap();
+    let (value, _) = backend.get("leases").unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&value).unwrap();
+    let lease = &json["instance"]["ConcurrencyInstance"]["leases"][0];
+    assert!(lease[0].is_string() && lease[1].is_string());
+    let leases = limiter.get_usage("leases").unwrap();
+    assert_eq!(leases.as_concurrency_instance().unwrap().leases().len(), 1);
+}